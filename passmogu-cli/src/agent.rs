@@ -0,0 +1,771 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::history::entry_index;
+use crate::input::wait_readable;
+use crate::lock::VaultLock;
+use crate::query::Filter;
+use crate::storage::{read_vault, save_vault, unlock_vault};
+use crate::store::{Existing, insert_form, prompts};
+use passmogu::{
+    header::Header,
+    otp::{Code, next_code},
+    search::search,
+    secret::Secret,
+    vault::{EncryptedVault, Field, FieldKind, HistoryEntry, Vault},
+};
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{self, Path, PathBuf};
+use std::time::Duration;
+
+// Requests and responses are messages: a count of parts, then each part as its length and bytes,
+// numbers are u32 big-endian. The bounds keep a misbehaving client from making the agent
+// allocate without limit.
+const MAX_PARTS: usize = 4096;
+const MAX_PART_LEN: usize = 1 << 20;
+
+// How long a client may take to send a request before the agent hangs up.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// $XDG_RUNTIME_DIR/passmogu/agent.sock, or passmogu-<uid>/agent.sock in the temp directory.
+pub(crate) fn socket_path() -> PathBuf {
+    let dir = match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(runtime) if runtime.is_absolute() => runtime.join("passmogu"),
+        // SAFETY: getuid can't fail
+        _ => env::temp_dir().join(format!("passmogu-{}", unsafe { libc::getuid() })),
+    };
+    dir.join("agent.sock")
+}
+
+/// Fails unless dir is owned by the current user and has mode 0700, so no one else could have
+/// put a socket in it.
+fn check_socket_dir(dir: &Path) -> Result<(), Error> {
+    let metadata = fs::symlink_metadata(dir)?;
+    // SAFETY: getuid can't fail
+    if metadata.uid() != unsafe { libc::getuid() } || metadata.mode() & 0o7777 != 0o700 {
+        return Err(Error::Agent(format!(
+            "{} must be a directory of yours with mode 0700",
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Fails unless the process on the other end of stream runs as the current user.
+fn check_peer(stream: &UnixStream) -> Result<(), Error> {
+    // SAFETY: getuid can't fail
+    if peer_uid(stream)? != unsafe { libc::getuid() } {
+        return Err(Error::Agent("peer belongs to another user".into()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len describe a ucred that outlives the call
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&raw mut cred).cast(),
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: uid and gid outlive the call
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// Runs passmogu agent: unlocks the vault at path once, then serves requests on socket_path
+/// until the configured auto_lock_timeout passes without one (never if it's zero), and forgets
+/// the key.
+pub(crate) fn run_agent(path: &Path, config: &Config, lock_timeout: Duration) -> Result<(), Error> {
+    let idle_timeout = config.auto_lock_timeout();
+    let socket = socket_path();
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(Error::AgentRunning(socket));
+    }
+    let (sealed, _, key) = unlock_vault(path, lock_timeout)?;
+    let agent = Agent {
+        path: path::absolute(path)?,
+        header: sealed.header().clone(),
+        key: LockedKey::new(key),
+        lock_timeout,
+        history_limit: config.history_limit,
+    };
+    let listener = bind(&socket)?;
+    println!("Agent serving {} on {}", path.display(), socket.display());
+    let served = agent.serve(&listener, idle_timeout);
+    let _ = fs::remove_file(&socket);
+    served?;
+    println!(
+        "Locked after {} seconds without requests",
+        idle_timeout.as_secs()
+    );
+    Ok(())
+}
+
+/// Creates the socket in a directory only the current user can enter, replacing a stale socket
+/// left behind by an agent that was killed.
+fn bind(socket: &Path) -> Result<UnixListener, Error> {
+    if let Some(dir) = socket.parent() {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        check_socket_dir(dir)?;
+    }
+    match fs::remove_file(socket) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => (),
+    }
+    let listener = UnixListener::bind(socket)?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// The data key, kept out of swap by mlock for as long as the agent holds it.
+struct LockedKey(Secret);
+
+impl LockedKey {
+    fn new(key: Secret) -> Self {
+        // SAFETY: the pointer and length describe the key's heap allocation, which doesn't move
+        // until the key is dropped
+        if unsafe { libc::mlock(key.expose().as_ptr().cast(), key.len()) } != 0 {
+            eprintln!(
+                "passmogu: couldn't lock the key in memory, it may be swapped out: {}",
+                io::Error::last_os_error()
+            );
+        }
+        // keeps other processes of the same user from reading the key with ptrace, and the key
+        // out of core dumps
+        #[cfg(target_os = "linux")]
+        // SAFETY: PR_SET_DUMPABLE only changes a flag of this process
+        unsafe {
+            libc::prctl(libc::PR_SET_DUMPABLE, 0);
+        }
+        LockedKey(key)
+    }
+
+    fn expose(&self) -> &[u8] {
+        self.0.expose()
+    }
+}
+
+impl Drop for LockedKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        // SAFETY: same allocation as in new
+        unsafe { libc::munlock(self.0.expose().as_ptr().cast(), self.0.len()) };
+    }
+}
+
+/// The server side. The vault file is read again for every request, so changes made by other
+/// processes are picked up, only the key stays in memory.
+struct Agent {
+    path: PathBuf,
+    // what the key was unlocked from, tells a changed key apart from a corrupt vault file
+    header: Header,
+    key: LockedKey,
+    lock_timeout: Duration,
+    history_limit: usize,
+}
+
+impl Agent {
+    fn serve(&self, listener: &UnixListener, idle_timeout: Duration) -> Result<(), Error> {
+        while wait_readable(listener.as_raw_fd(), idle_timeout)? {
+            let (stream, _) = listener.accept()?;
+            // a broken connection only affects that client
+            if let Err(e) = check_peer(&stream).and_then(|()| self.serve_connection(stream)) {
+                eprintln!("passmogu: agent connection failed: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers requests on stream until the client hangs up.
+    fn serve_connection(&self, mut stream: UnixStream) -> Result<(), Error> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        while let Some(request) = read_message(&mut stream)? {
+            match self.respond(&request) {
+                Ok(response) => {
+                    let mut parts: Vec<&[u8]> = vec![b"ok"];
+                    parts.extend(response.iter().map(Secret::expose));
+                    write_message(&mut stream, &parts)?;
+                }
+                Err(e) => write_message(&mut stream, &[b"error", e.to_string().as_bytes()])?,
+            }
+        }
+        Ok(())
+    }
+
+    fn respond(&self, request: &[Secret]) -> Result<Vec<Secret>, Error> {
+        let bytes = |data: &[u8]| Secret::new(data.into());
+        let parts: Vec<&[u8]> = request.iter().map(Secret::expose).collect();
+        match parts.as_slice() {
+            [b"vault"] => Ok(vec![bytes(self.path.as_os_str().as_bytes())]),
+            [b"list", tag, folder, favorites] => {
+                // empty parts don't filter
+                let text = |part: &[u8]| {
+                    (!part.is_empty()).then(|| String::from_utf8_lossy(part).into_owned())
+                };
+                let filter = Filter {
+                    tag: text(tag),
+                    folder: text(folder),
+                    favorites: !favorites.is_empty(),
+                };
+                let vault = self.open()?;
+                Ok(filter.names(&vault).into_iter().map(bytes).collect())
+            }
+            [b"find", query] => {
+                let vault = self.open()?;
+                let hits = search(&vault, self.key.expose(), query)?;
+                let mut response = Vec::with_capacity(2 * hits.len());
+                for hit in hits {
+                    response.push(bytes(hit.name));
+                    response.push(bytes(hit.matched.name().as_bytes()));
+                }
+                Ok(response)
+            }
+            [b"get", form] => {
+                let form = self.open()?.get_and_open(form, self.key.expose())?;
+                let mut response = Vec::with_capacity(3 * form.len());
+                for field in form {
+                    response.push(bytes(kind_name(field.kind)));
+                    response.push(field.prompt);
+                    response.push(field.answer);
+                }
+                Ok(response)
+            }
+            [b"store", form, existing, fields @ ..] if fields.len().is_multiple_of(3) => {
+                let existing = match *existing {
+                    b"keep" => Existing::Keep,
+                    b"replace" => Existing::Replace,
+                    b"merge" => Existing::Merge,
+                    _ => return Err(Error::Agent("malformed request".into())),
+                };
+                let fields = fields
+                    .chunks_exact(3)
+                    .map(|field| {
+                        Ok(Field {
+                            kind: parse_kind(field[0])?,
+                            prompt: bytes(field[1]),
+                            answer: bytes(field[2]),
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?;
+                let _lock = VaultLock::acquire(&self.path, self.lock_timeout)?;
+                let mut vault = self.open()?;
+                let overwritten =
+                    insert_form(&mut vault, self.key.expose(), form, fields, existing)?;
+                save_vault(
+                    &self.path,
+                    &EncryptedVault::seal(&vault, self.key.expose())?,
+                )?;
+                Ok(match overwritten {
+                    Some(old) => vec![bytes(b"overwrote"), bytes(prompts(&old).as_bytes())],
+                    None => vec![bytes(b"stored")],
+                })
+            }
+            [b"otp", form, time, prompt @ ..] if prompt.len() <= 1 => {
+                let time = str::from_utf8(time)
+                    .ok()
+                    .and_then(|time| time.parse().ok())
+                    .ok_or_else(|| Error::Agent("malformed request".into()))?;
+                // a HOTP counter is written back
+                let _lock = VaultLock::acquire(&self.path, self.lock_timeout)?;
+                let mut vault = self.open()?;
+                let code = next_code(
+                    &mut vault,
+                    self.key.expose(),
+                    form,
+                    prompt.first().copied(),
+                    time,
+                )?;
+                if code.counter.is_some() {
+                    save_vault(
+                        &self.path,
+                        &EncryptedVault::seal(&vault, self.key.expose())?,
+                    )?;
+                }
+                let number =
+                    |n: Option<u64>| bytes(n.map(|n| n.to_string()).unwrap_or_default().as_bytes());
+                Ok(vec![
+                    bytes(code.code.as_bytes()),
+                    number(code.remaining),
+                    number(code.counter),
+                ])
+            }
+            [b"history", form] => {
+                let vault = self.open()?;
+                vault.get(form).ok_or(passmogu::Error::FormNotFound)?;
+                let history = vault.history(form, self.key.expose())?;
+                let mut response = Vec::with_capacity(4 * history.len());
+                for entry in history {
+                    response.push(bytes(entry.replaced.to_string().as_bytes()));
+                    response.push(bytes(kind_name(entry.field.kind)));
+                    response.push(entry.field.prompt);
+                    response.push(entry.field.answer);
+                }
+                Ok(response)
+            }
+            [b"restore", form, number] => {
+                let number = str::from_utf8(number)
+                    .ok()
+                    .and_then(|number| number.parse().ok())
+                    .ok_or_else(|| Error::Agent("malformed request".into()))?;
+                let _lock = VaultLock::acquire(&self.path, self.lock_timeout)?;
+                let mut vault = self.open()?;
+                let history = vault.history(form, self.key.expose())?;
+                let index = entry_index(history.len(), number)?;
+                vault.restore(form, self.key.expose(), index)?;
+                save_vault(
+                    &self.path,
+                    &EncryptedVault::seal(&vault, self.key.expose())?,
+                )?;
+                Ok(Vec::new())
+            }
+            _ => Err(Error::Agent("malformed request".into())),
+        }
+    }
+
+    fn open(&self) -> Result<Vault, Error> {
+        let sealed = read_vault(&self.path)?;
+        match sealed.open(self.key.expose()) {
+            Err(passmogu::Error::Authentication) if *sealed.header() != self.header => {
+                Err(Error::Agent(
+                    "the vault's key was changed since the agent unlocked it, restart the agent"
+                        .into(),
+                ))
+            }
+            Err(passmogu::Error::Authentication) => Err(Error::Agent(
+                "the vault file is corrupt, passmogu restore-backup puts back a backup".into(),
+            )),
+            vault => {
+                let mut vault = vault?;
+                vault.set_history_limit(self.history_limit);
+                Ok(vault)
+            }
+        }
+    }
+}
+
+/// The client side, used by one-shot commands.
+pub(crate) struct Client {
+    stream: UnixStream,
+}
+
+impl Client {
+    /// Connects to the running agent if it serves the vault at path.
+    /// None if no agent is running or it serves a different vault.
+    /// Nothing is sent unless the socket's directory and the agent belong to the current user.
+    pub(crate) fn connect(path: &Path) -> Result<Option<Client>, Error> {
+        let socket = socket_path();
+        let Ok(stream) = UnixStream::connect(&socket) else {
+            return Ok(None);
+        };
+        if let Some(dir) = socket.parent() {
+            check_socket_dir(dir)?;
+        }
+        check_peer(&stream)?;
+        let mut client = Client { stream };
+        let served = client.request(&[b"vault"])?;
+        let path = path::absolute(path)?;
+        let serves_path =
+            matches!(served.as_slice(), [served] if served.expose() == path.as_os_str().as_bytes());
+        Ok(serves_path.then_some(client))
+    }
+
+    /// Names of the forms that match filter, sorted.
+    pub(crate) fn list(&mut self, filter: &Filter) -> Result<Vec<Secret>, Error> {
+        let tag = filter.tag.as_deref().unwrap_or_default();
+        let folder = filter.folder.as_deref().unwrap_or_default();
+        let favorites: &[u8] = if filter.favorites { b"1" } else { b"" };
+        self.request(&[b"list", tag.as_bytes(), folder.as_bytes(), favorites])
+    }
+
+    /// Forms that match query like passmogu::search::search, with the name of the part that
+    /// matched.
+    pub(crate) fn find(&mut self, query: &[u8]) -> Result<Vec<(Secret, String)>, Error> {
+        let response = self.request(&[b"find", query])?;
+        if !response.len().is_multiple_of(2) {
+            return Err(Error::Agent("malformed response".into()));
+        }
+        let mut parts = response.into_iter();
+        let mut hits = Vec::new();
+        while let (Some(name), Some(matched)) = (parts.next(), parts.next()) {
+            hits.push((name, String::from_utf8_lossy(matched.expose()).into()));
+        }
+        Ok(hits)
+    }
+
+    pub(crate) fn get(&mut self, form: &[u8]) -> Result<Vec<Field>, Error> {
+        let response = self.request(&[b"get", form])?;
+        if !response.len().is_multiple_of(3) {
+            return Err(Error::Agent("malformed response".into()));
+        }
+        let mut parts = response.into_iter();
+        let mut fields = Vec::new();
+        while let (Some(kind), Some(prompt), Some(answer)) =
+            (parts.next(), parts.next(), parts.next())
+        {
+            let kind = parse_kind(kind.expose())?;
+            fields.push(Field {
+                prompt,
+                answer,
+                kind,
+            });
+        }
+        Ok(fields)
+    }
+
+    /// Stores form like store::insert_form, returns the prompts of the form it replaced.
+    pub(crate) fn store(
+        &mut self,
+        name: &[u8],
+        form: Vec<Field>,
+        existing: Existing,
+    ) -> Result<Option<String>, Error> {
+        let existing: &[u8] = match existing {
+            Existing::Keep => b"keep",
+            Existing::Replace => b"replace",
+            Existing::Merge => b"merge",
+        };
+        let mut request: Vec<&[u8]> = vec![b"store", name, existing];
+        for field in &form {
+            request.push(kind_name(field.kind));
+            request.push(field.prompt.expose());
+            request.push(field.answer.expose());
+        }
+        let response = self.request(&request)?;
+        match response.as_slice() {
+            [stored] if stored.expose() == b"stored" => Ok(None),
+            [overwrote, prompts] if overwrote.expose() == b"overwrote" => {
+                Ok(Some(String::from_utf8_lossy(prompts.expose()).into()))
+            }
+            _ => Err(Error::Agent("malformed response".into())),
+        }
+    }
+
+    /// One-time code like passmogu::otp::next_code, the agent saves a HOTP counter.
+    pub(crate) fn otp(
+        &mut self,
+        form: &[u8],
+        prompt: Option<&[u8]>,
+        time: u64,
+    ) -> Result<Code, Error> {
+        let time = time.to_string();
+        let mut request: Vec<&[u8]> = vec![b"otp", form, time.as_bytes()];
+        request.extend(prompt);
+        let response = self.request(&request)?;
+        let number = |part: &Secret| -> Result<Option<u64>, Error> {
+            if part.is_empty() {
+                return Ok(None);
+            }
+            str::from_utf8(part.expose())
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .map(Some)
+                .ok_or_else(|| Error::Agent("malformed response".into()))
+        };
+        match response.as_slice() {
+            [code, remaining, counter] => Ok(Code {
+                code: String::from_utf8_lossy(code.expose()).into(),
+                remaining: number(remaining)?,
+                counter: number(counter)?,
+            }),
+            _ => Err(Error::Agent("malformed response".into())),
+        }
+    }
+
+    /// Previous fields of form like Vault::history, oldest first.
+    pub(crate) fn history(&mut self, form: &[u8]) -> Result<Vec<HistoryEntry>, Error> {
+        let response = self.request(&[b"history", form])?;
+        if !response.len().is_multiple_of(4) {
+            return Err(Error::Agent("malformed response".into()));
+        }
+        let mut parts = response.into_iter();
+        let mut entries = Vec::new();
+        while let (Some(replaced), Some(kind), Some(prompt), Some(answer)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        {
+            let replaced = str::from_utf8(replaced.expose())
+                .ok()
+                .and_then(|replaced| replaced.parse().ok())
+                .ok_or_else(|| Error::Agent("malformed response".into()))?;
+            let kind = parse_kind(kind.expose())?;
+            entries.push(HistoryEntry {
+                field: Field {
+                    prompt,
+                    answer,
+                    kind,
+                },
+                replaced,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Restores entry number of form's history, numbered newest first like passmogu history.
+    pub(crate) fn restore(&mut self, form: &[u8], number: usize) -> Result<(), Error> {
+        let number = number.to_string();
+        self.request(&[b"restore", form, number.as_bytes()])?;
+        Ok(())
+    }
+
+    fn request(&mut self, parts: &[&[u8]]) -> Result<Vec<Secret>, Error> {
+        write_message(&mut self.stream, parts)?;
+        let malformed = || Error::Agent("malformed response".into());
+        let mut response = read_message(&mut self.stream)?
+            .ok_or_else(malformed)?
+            .into_iter();
+        let status = response.next().ok_or_else(malformed)?;
+        match status.expose() {
+            b"ok" => Ok(response.collect()),
+            b"error" => {
+                let message = response.next().ok_or_else(malformed)?;
+                Err(Error::Agent(
+                    String::from_utf8_lossy(message.expose()).into(),
+                ))
+            }
+            _ => Err(malformed()),
+        }
+    }
+}
+
+fn write_message(output: &mut impl Write, parts: &[&[u8]]) -> Result<(), Error> {
+    // built up in one buffer so a message is written all at once
+    let len = 4 + parts.iter().map(|part| 4 + part.len()).sum::<usize>();
+    let mut message = Secret::zero(len);
+    let mut at = 0;
+    let mut put = |data: &[u8]| {
+        message.expose_mut()[at..at + data.len()].copy_from_slice(data);
+        at += data.len();
+    };
+    put(&encode_len(parts.len())?);
+    for part in parts {
+        put(&encode_len(part.len())?);
+        put(part);
+    }
+    output.write_all(message.expose())?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Fields are sent as kind, prompt and answer, an empty kind stands for None.
+fn kind_name(kind: Option<FieldKind>) -> &'static [u8] {
+    kind.map_or("", FieldKind::name).as_bytes()
+}
+
+fn parse_kind(name: &[u8]) -> Result<Option<FieldKind>, Error> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+    FieldKind::from_name(name)
+        .map(Some)
+        .ok_or_else(|| Error::Agent("unknown field kind".into()))
+}
+
+/// Reads a message written by write_message, None if input ended before it started.
+fn read_message(input: &mut impl Read) -> Result<Option<Vec<Secret>>, Error> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        read => read?,
+    }
+    let count = decode_len(len, MAX_PARTS)?;
+    let mut parts = Vec::with_capacity(count);
+    for _ in 0..count {
+        input.read_exact(&mut len)?;
+        let mut part = Secret::zero(decode_len(len, MAX_PART_LEN)?);
+        input.read_exact(part.expose_mut())?;
+        parts.push(part);
+    }
+    Ok(Some(parts))
+}
+
+fn encode_len(len: usize) -> Result<[u8; 4], Error> {
+    if len > MAX_PART_LEN.max(MAX_PARTS) {
+        return Err(Error::Agent("message too large".into()));
+    }
+    Ok((len as u32).to_be_bytes())
+}
+
+fn decode_len(len: [u8; 4], max: usize) -> Result<usize, Error> {
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max {
+        return Err(Error::Agent("message too large".into()));
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, field, test_header};
+    use std::thread;
+
+    #[test]
+    fn message_round_trip() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &[b"get", b"", b"with\nnewline"]).unwrap();
+        write_message(&mut buffer, &[]).unwrap();
+        let mut input = buffer.as_slice();
+        let parts = read_message(&mut input).unwrap().unwrap();
+        let parts: Vec<&[u8]> = parts.iter().map(Secret::expose).collect();
+        assert_eq!(parts, [&b"get"[..], b"", b"with\nnewline"]);
+        assert!(read_message(&mut input).unwrap().unwrap().is_empty());
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        // truncated and oversized messages
+        assert!(read_message(&mut &buffer[..10]).is_err());
+        let huge = ((MAX_PARTS + 1) as u32).to_be_bytes();
+        assert!(matches!(
+            read_message(&mut huge.as_slice()),
+            Err(Error::Agent(_))
+        ));
+    }
+
+    #[test]
+    fn private_socket_dir_and_peer() {
+        let dir = TempDir::new("agent-dir");
+        fs::set_permissions(&dir.0, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(matches!(check_socket_dir(&dir.0), Err(Error::Agent(_))));
+        fs::set_permissions(&dir.0, fs::Permissions::from_mode(0o700)).unwrap();
+        check_socket_dir(&dir.0).unwrap();
+
+        let (client, _server) = UnixStream::pair().unwrap();
+        check_peer(&client).unwrap();
+    }
+
+    #[test]
+    fn serve_requests() {
+        let dir = TempDir::new("agent");
+        let path = dir.0.join("vault");
+        let (header, key) = test_header();
+        let vault = Vault::new(header);
+        let key_copy = Secret::new(key.expose().into());
+        save_vault(&path, &EncryptedVault::seal(&vault, key.expose()).unwrap()).unwrap();
+
+        let agent = Agent {
+            path: path.clone(),
+            header: vault.header().clone(),
+            key: LockedKey(key),
+            lock_timeout: Duration::ZERO,
+            history_limit: 10,
+        };
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || agent.serve_connection(server).unwrap());
+        let mut client = Client { stream: client };
+
+        let password = Field {
+            kind: Some(FieldKind::Password),
+            ..field(b"password", b"hunter2")
+        };
+        let stored = client.store(b"irc", vec![password.clone()], Existing::Keep);
+        assert_eq!(stored.unwrap(), None);
+        assert!(matches!(
+            client.store(b"irc", vec![], Existing::Keep),
+            Err(Error::Agent(_))
+        ));
+        let stored = client.store(b"irc", vec![field(b"user", b"me")], Existing::Merge);
+        assert_eq!(stored.unwrap().as_deref(), Some("password"));
+
+        let names = client.list(&Filter::default()).unwrap();
+        assert_eq!(names, [Secret::new((*b"irc").into())]);
+        let favorites = Filter {
+            favorites: true,
+            ..Filter::default()
+        };
+        assert_eq!(client.list(&favorites).unwrap(), []);
+        let found = client.find(b"usr").unwrap();
+        assert_eq!(found, [(Secret::new((*b"irc").into()), "prompt".into())]);
+        assert_eq!(client.find(b"nope").unwrap(), []);
+        let fields = client.get(b"irc").unwrap();
+        assert_eq!(fields, [password.clone(), field(b"user", b"me")]);
+        assert!(matches!(client.get(b"nope"), Err(Error::Agent(_))));
+
+        // HOTP counters are saved by the agent
+        let uri = b"otpauth://hotp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0";
+        let stored = client.store(b"bank", vec![field(b"2fa", uri)], Existing::Keep);
+        assert_eq!(stored.unwrap(), None);
+        let code = client.otp(b"bank", None, 0).unwrap();
+        assert_eq!((code.code.as_str(), code.counter), ("755224", Some(0)));
+        let code = client.otp(b"bank", Some(b"2fa"), 0).unwrap();
+        assert_eq!((code.code.as_str(), code.counter), ("287082", Some(1)));
+        assert!(matches!(client.otp(b"irc", None, 0), Err(Error::Agent(_))));
+
+        // replacing the password keeps the old one, which can be restored
+        let new_password = Field {
+            kind: Some(FieldKind::Password),
+            ..field(b"password", b"hunter3")
+        };
+        let stored = client.store(b"irc", vec![new_password], Existing::Merge);
+        assert!(stored.unwrap().is_some());
+        let history = client.history(b"irc").unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].field, password);
+        assert!(client.history(b"nope").is_err());
+        assert!(client.restore(b"irc", 2).is_err());
+        client.restore(b"irc", 1).unwrap();
+        assert_eq!(client.get(b"irc").unwrap()[0], password);
+        assert_eq!(client.history(b"irc").unwrap().len(), 2);
+
+        // the agent wrote the vault file
+        let vault = read_vault(&path).unwrap().open(key_copy.expose()).unwrap();
+        assert!(vault.get(b"irc").is_some());
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn changed_key_or_corrupt_file() {
+        let dir = TempDir::new("agent-open");
+        let path = dir.0.join("vault");
+        let (header, key) = test_header();
+        let sealed = EncryptedVault::seal(&Vault::new(header.clone()), key.expose()).unwrap();
+        let agent = Agent {
+            path: path.clone(),
+            header,
+            key: LockedKey(key),
+            lock_timeout: Duration::ZERO,
+            history_limit: 10,
+        };
+        let failure = || match agent.open() {
+            Err(Error::Agent(message)) => message,
+            opened => panic!("{opened:?}"),
+        };
+
+        let mut dumped = sealed.dump().into_vec();
+        let last = dumped.len() - 2;
+        dumped[last] = if dumped[last] == b'0' { b'1' } else { b'0' };
+        fs::write(&path, &dumped).unwrap();
+        assert!(failure().contains("corrupt"));
+
+        let kdf = sealed.header().kdf;
+        let rotated = sealed.rotate_key(b"hunter2", b"hunter3", kdf).unwrap();
+        save_vault(&path, &rotated).unwrap();
+        assert!(failure().contains("key was changed"));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::error::Error;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// Programs that set the clipboard to what they read from stdin, tried in order.
+const PROGRAMS: [&[&str]; 4] = [
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["pbcopy"],
+];
+
+// Counts calls to copy_for, so a timer can tell whether its copy is still on the clipboard.
+static COPIES: AtomicU64 = AtomicU64::new(0);
+
+// Number of the last copy that hasn't been cleared yet, if any.
+static PENDING: Mutex<Option<u64>> = Mutex::new(None);
+
+/// Puts data on the clipboard and clears it after timeout (never if it's zero), unless something
+/// else was copied in the meantime. Clearing happens on a background thread, so call
+/// clear_pending before exiting.
+pub(crate) fn copy_for(data: &[u8], timeout: Duration) -> Result<(), Error> {
+    copy(data)?;
+    let copy_number = COPIES.fetch_add(1, Ordering::Relaxed);
+    *PENDING.lock().unwrap_or_else(|e| e.into_inner()) = Some(copy_number);
+    if !timeout.is_zero() {
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+            if *pending == Some(copy_number) {
+                let _ = copy(b"");
+                *pending = None;
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Clears the clipboard right away if copy_for left something on it.
+pub(crate) fn clear_pending() -> Result<(), Error> {
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    if pending.take().is_some() {
+        copy(b"")?;
+    }
+    Ok(())
+}
+
+/// Runs the first program in PROGRAMS that's installed, with data as its input.
+fn copy(data: &[u8]) -> Result<(), Error> {
+    for program in PROGRAMS {
+        let child = Command::new(program[0])
+            .args(&program[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            continue;
+        };
+        // dropping stdin closes it so the program sees the end of its input
+        let written = child.stdin.take().map(|mut stdin| stdin.write_all(data));
+        if child.wait()?.success() {
+            written.transpose()?;
+            return Ok(());
+        }
+    }
+    Err(Error::NoClipboard)
+}
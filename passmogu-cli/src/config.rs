@@ -0,0 +1,247 @@
+use crate::error::Error;
+use crate::registry::Registry;
+use passmogu::generate::{CharClass, DEFAULT_SYMBOLS, PasswordPolicy};
+use passmogu::vault::DEFAULT_HISTORY_LIMIT;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Settings read from config.toml. Every key is optional, e.g.
+/// ```toml
+/// vault = "/home/me/sync/passmogu.vault"
+/// clipboard_timeout = 45  # seconds, 0 never clears
+/// auto_lock_timeout = 300 # seconds, 0 never locks
+/// history_limit = 10      # previous answers kept per field, 0 keeps none
+///
+/// [generator]
+/// length = 24
+/// symbols = false
+/// exclude_lookalikes = true
+/// ```
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// vault file or registered name used when none is given otherwise
+    pub(crate) vault: Option<PathBuf>,
+    /// seconds until an answer copied by the session is cleared from the clipboard
+    pub(crate) clipboard_timeout: u64,
+    /// seconds without input until the session locks the vault
+    pub(crate) auto_lock_timeout: u64,
+    /// how many previous answers of each field a vault keeps
+    pub(crate) history_limit: usize,
+    pub(crate) generator: Generator,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            vault: None,
+            clipboard_timeout: 45,
+            auto_lock_timeout: 300,
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            generator: Generator::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file at path, or at default_config_path if path is None.
+    /// A missing default config file means the defaults, a missing explicit one is an error.
+    pub(crate) fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let text = match fs::read_to_string(&path) {
+            Err(e) if !explicit && e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default());
+            }
+            Err(e) => return Err(Error::Config(path, e.to_string())),
+            Ok(text) => text,
+        };
+        toml::from_str(&text).map_err(|e| Error::Config(path, e.message().into()))
+    }
+
+    /// Picks the vault file: the one given on the command line (or by PASSMOGU_VAULT), the one in
+    /// the config file, the registry's default, or default_vault_path, in that order.
+    /// Names of registered vaults are accepted in place of paths.
+    pub(crate) fn vault_path(
+        &self,
+        arg: Option<&Path>,
+        registry: &Registry,
+    ) -> Result<PathBuf, Error> {
+        if let Some(vault) = arg.or(self.vault.as_deref()) {
+            return registry.resolve(vault);
+        }
+        registry
+            .default_path()
+            .map(Path::to_path_buf)
+            .or_else(default_vault_path)
+            .ok_or(Error::NoVaultPath)
+    }
+
+    pub(crate) fn clipboard_timeout(&self) -> Duration {
+        Duration::from_secs(self.clipboard_timeout)
+    }
+
+    pub(crate) fn auto_lock_timeout(&self) -> Duration {
+        Duration::from_secs(self.auto_lock_timeout)
+    }
+}
+
+/// The [generator] table, the policy used by gen and @generate.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Generator {
+    /// used when no length is given
+    pub(crate) length: usize,
+    // which character classes may appear
+    lowercase: bool,
+    uppercase: bool,
+    digits: bool,
+    symbols: bool,
+    /// replaces DEFAULT_SYMBOLS
+    symbol_set: Option<String>,
+    exclude_lookalikes: bool,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            symbol_set: None,
+            exclude_lookalikes: false,
+        }
+    }
+}
+
+impl Generator {
+    /// The configured policy for passwords of exactly len characters.
+    pub(crate) fn policy(&self, len: usize) -> PasswordPolicy {
+        let mut policy = PasswordPolicy::default()
+            .length(len, len)
+            .symbols(
+                self.symbol_set
+                    .as_deref()
+                    .map_or(DEFAULT_SYMBOLS, str::as_bytes),
+            )
+            .exclude_lookalikes(self.exclude_lookalikes);
+        let classes = [
+            (CharClass::Lowercase, self.lowercase),
+            (CharClass::Uppercase, self.uppercase),
+            (CharClass::Digit, self.digits),
+            (CharClass::Symbol, self.symbols),
+        ];
+        for (class, allowed) in classes {
+            if !allowed {
+                policy = policy.forbid(class);
+            }
+        }
+        policy
+    }
+}
+
+/// $XDG_CONFIG_HOME/passmogu/config.toml
+pub(crate) fn default_config_path() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("passmogu").join("config.toml"))
+}
+
+/// $XDG_DATA_HOME/passmogu/vault
+pub(crate) fn default_vault_path() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join("passmogu").join("vault"))
+}
+
+/// The directory in the environment variable var, or $HOME/fallback if it's unset.
+/// The XDG base directory spec says relative paths are invalid and must be ignored.
+pub(crate) fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let absolute = |dir: &PathBuf| dir.is_absolute();
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(absolute)
+        .or_else(|| {
+            let home = PathBuf::from(env::var_os("HOME")?);
+            absolute(&home).then(|| home.join(fallback))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+        let config: Config = toml::from_str(
+            "vault = \"/tmp/v\"\n\
+             auto_lock_timeout = 0\n\
+             history_limit = 3\n\
+             [generator]\n\
+             length = 8\n\
+             symbols = false\n",
+        )
+        .unwrap();
+        assert_eq!(config.vault, Some(PathBuf::from("/tmp/v")));
+        assert_eq!(config.auto_lock_timeout(), Duration::ZERO);
+        assert_eq!(config.clipboard_timeout(), Duration::from_secs(45));
+        assert_eq!(config.history_limit, 3);
+        assert_eq!(config.generator.length, 8);
+        let password = config.generator.policy(30).generate().unwrap();
+        assert_eq!(password.len(), 30);
+        assert!(password.expose().iter().all(u8::is_ascii_alphanumeric));
+
+        assert!(toml::from_str::<Config>("vualt = \"/tmp/v\"").is_err());
+        assert!(toml::from_str::<Config>("[generator]\nlength = \"long\"").is_err());
+    }
+
+    #[test]
+    fn vault_path_precedence() {
+        let mut config = Config::default();
+        let mut registry = Registry::default();
+        let arg = Path::new("/a/arg.vault");
+        assert_eq!(config.vault_path(Some(arg), &registry).unwrap(), arg);
+        config.vault = Some(PathBuf::from("/c/config.vault"));
+        assert_eq!(config.vault_path(Some(arg), &registry).unwrap(), arg);
+        assert_eq!(
+            config.vault_path(None, &registry).unwrap(),
+            Path::new("/c/config.vault")
+        );
+        registry.add("work", Path::new("/w/vault")).unwrap();
+        // the config file outranks the registry's default
+        assert_eq!(
+            config.vault_path(None, &registry).unwrap(),
+            Path::new("/c/config.vault")
+        );
+        assert_eq!(
+            config
+                .vault_path(Some(Path::new("work")), &registry)
+                .unwrap(),
+            Path::new("/w/vault")
+        );
+        config.vault = Some(PathBuf::from("work"));
+        assert_eq!(
+            config.vault_path(None, &registry).unwrap(),
+            Path::new("/w/vault")
+        );
+        config.vault = None;
+        assert_eq!(
+            config.vault_path(None, &registry).unwrap(),
+            Path::new("/w/vault")
+        );
+        registry.remove("work").unwrap();
+        config.vault = Some(PathBuf::from("work"));
+        assert!(matches!(
+            config.vault_path(None, &registry),
+            Err(Error::UnknownVault(_))
+        ));
+    }
+}
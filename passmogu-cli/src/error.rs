@@ -1,13 +1,142 @@
+use passmogu::vault::FieldKind;
+use std::fmt;
 use std::io;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub(crate) enum Error {
-    IOError,
-    // UnlockError,
+    Io(io::Error),
+    // vault couldn't be parsed, unlocked or opened
+    Vault(passmogu::Error),
+    // restore-backup found no backup that parses
+    NoBackup,
+    // vault's blob doesn't decrypt with the data key its header unwrapped
+    CorruptBlob,
+    // config file at this path couldn't be read or parsed, with the reason
+    Config(PathBuf, String),
+    // vault registry has no vault with this name
+    UnknownVault(String),
+    // vault registry already has a vault with this name
+    VaultNameTaken(String),
+    // vault names can't be empty or contain path separators
+    InvalidVaultName(String),
+    // session has no unlocked vault with this name
+    VaultNotOpen(String),
+    // no --vault, PASSMOGU_VAULT, config entry or home directory to pick a vault file from
+    NoVaultPath,
+    // none of the clipboard programs could be run
+    NoClipboard,
+    // the agent refused a request or sent something unexpected, with the reason
+    Agent(String),
+    // an agent is already listening on the socket at this path
+    AgentRunning(PathBuf),
+    // another process holds the lock file at this path
+    Locked(PathBuf),
+    // vault file at this path was written by another process since the session loaded it
+    VaultChanged(PathBuf),
+    // init would overwrite an existing file
+    VaultExists(PathBuf),
+    // store would overwrite an existing form without --replace or --merge-fields
+    FormExists(String),
+    // session input line longer than this many bytes
+    LineTooLong(usize),
+    // session input line has a quote without its closing quote
+    UnterminatedQuote,
+    // form has no field with this prompt
+    FieldNotFound(String),
+    // no prompt was given and the form has no password field to default to
+    NoPasswordField,
+    // not the name of a passmogu::vault::FieldKind, nor none
+    UnknownFieldKind(String),
+    // password length isn't a number, or is over passmogu::generate::MAX_LENGTH
+    InvalidLength,
+    // --field argument with this prompt isn't PROMPT=ANSWER, the answer is left out
+    InvalidField(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Vault(e) => write!(f, "{e}"),
+            Error::NoBackup => write!(f, "no valid backup found"),
+            Error::CorruptBlob => write!(
+                f,
+                "the master password is right, but the vault's data doesn't decrypt"
+            ),
+            Error::Config(path, message) => {
+                write!(f, "config file {}: {message}", path.display())
+            }
+            Error::UnknownVault(name) => write!(f, "no vault named {name}"),
+            Error::VaultNameTaken(name) => write!(f, "there's already a vault named {name}"),
+            Error::InvalidVaultName(name) => write!(
+                f,
+                "invalid vault name {name:?}, names can't be empty or contain slashes"
+            ),
+            Error::VaultNotOpen(name) => {
+                write!(f, "vault {name} isn't unlocked, open it first")
+            }
+            Error::NoVaultPath => write!(f, "no vault file given, pass --vault or set HOME"),
+            Error::NoClipboard => write!(
+                f,
+                "couldn't copy to the clipboard, install wl-copy, xclip or xsel"
+            ),
+            Error::Agent(message) => write!(f, "agent: {message}"),
+            Error::AgentRunning(socket) => {
+                write!(f, "an agent is already running on {}", socket.display())
+            }
+            Error::Locked(path) => write!(
+                f,
+                "vault is in use by another process (locked by {}), pass --wait to wait for it",
+                path.display()
+            ),
+            Error::VaultChanged(path) => write!(
+                f,
+                "{} changed on disk since it was unlocked, save! overwrites those changes",
+                path.display()
+            ),
+            Error::VaultExists(path) => write!(
+                f,
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            ),
+            Error::FormExists(name) => write!(
+                f,
+                "form {name} already exists, pass --replace or --merge-fields"
+            ),
+            Error::LineTooLong(len) => write!(f, "line is longer than {len} bytes"),
+            Error::UnterminatedQuote => write!(f, "unterminated quote"),
+            Error::FieldNotFound(prompt) => write!(f, "no field {prompt:?}"),
+            Error::NoPasswordField => write!(f, "form has no password field, give a prompt"),
+            Error::UnknownFieldKind(kind) => {
+                let kinds: Vec<_> = FieldKind::ALL.iter().map(|kind| kind.name()).collect();
+                write!(
+                    f,
+                    "unknown field kind {kind:?}, expected none or one of {}",
+                    kinds.join(", ")
+                )
+            }
+            Error::InvalidLength => write!(
+                f,
+                "length must be a number up to {}",
+                passmogu::generate::MAX_LENGTH
+            ),
+            Error::InvalidField(prompt) => write!(
+                f,
+                "invalid field {prompt:?}, expected PROMPT=ANSWER, PROMPT=@generate[:LENGTH] or PROMPT=@-"
+            ),
+        }
+    }
 }
 
 impl From<io::Error> for Error {
-    fn from(_: io::Error) -> Self {
-        Error::IOError
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<passmogu::Error> for Error {
+    fn from(e: passmogu::Error) -> Self {
+        Error::Vault(e)
     }
 }
@@ -0,0 +1,107 @@
+#[cfg(unix)]
+use crate::agent;
+use crate::error::Error;
+use crate::lock::VaultLock;
+use crate::session::format_time;
+use crate::storage::{save_vault, unlock_locked_vault, unlock_vault};
+use passmogu::vault::{EncryptedVault, HistoryEntry};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Prints the history of form, see print_entries.
+/// Goes through the agent if it's running for this vault, otherwise asks for the master password.
+pub(crate) fn print_history(
+    path: &Path,
+    form: &str,
+    reveal: bool,
+    lock_timeout: Duration,
+) -> Result<(), Error> {
+    #[cfg(unix)]
+    if let Some(mut agent) = agent::Client::connect(path)? {
+        return print_entries(&agent.history(form.as_bytes())?, reveal);
+    }
+    let (_, vault, key) = unlock_vault(path, lock_timeout)?;
+    vault
+        .get(form.as_bytes())
+        .ok_or(passmogu::Error::FormNotFound)?;
+    print_entries(&vault.history(form.as_bytes(), key.expose())?, reveal)
+}
+
+/// Puts the answer of entry number (as numbered by print_entries) back into form.
+/// Goes through the agent like print_history. history_limit applies when not using the agent.
+pub(crate) fn restore_entry(
+    path: &Path,
+    form: &str,
+    number: usize,
+    lock_timeout: Duration,
+    history_limit: usize,
+) -> Result<(), Error> {
+    #[cfg(unix)]
+    if let Some(mut agent) = agent::Client::connect(path)? {
+        agent.restore(form.as_bytes(), number)?;
+        println!("Restored entry {number} of {form}");
+        return Ok(());
+    }
+    let lock = VaultLock::acquire(path, lock_timeout)?;
+    let (_, mut vault, key) = unlock_locked_vault(path, &lock)?;
+    vault.set_history_limit(history_limit);
+    let history = vault.history(form.as_bytes(), key.expose())?;
+    let index = entry_index(history.len(), number)?;
+    vault.restore(form.as_bytes(), key.expose(), index)?;
+    save_vault(path, &EncryptedVault::seal(&vault, key.expose())?)?;
+    println!("Restored entry {number} of {form}");
+    Ok(())
+}
+
+/// Lists entries newest first, numbered from 1, with when they were replaced and their prompt.
+/// Answers are only printed if reveal is set.
+pub(crate) fn print_entries(entries: &[HistoryEntry], reveal: bool) -> Result<(), Error> {
+    if entries.is_empty() {
+        println!("No history");
+        return Ok(());
+    }
+    let mut stdout = io::stdout();
+    for (number, entry) in entries.iter().rev().enumerate() {
+        let field = &entry.field;
+        write!(
+            stdout,
+            "{:>3}  {}  {}",
+            number + 1,
+            format_time(entry.replaced),
+            String::from_utf8_lossy(field.prompt.expose())
+        )?;
+        if let Some(kind) = field.kind {
+            write!(stdout, " ({kind})")?;
+        }
+        if reveal {
+            stdout.write_all(b": ")?;
+            stdout.write_all(field.answer.expose())?;
+        }
+        stdout.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Turns the number print_entries gave an entry back into an index for Vault::restore.
+pub(crate) fn entry_index(len: usize, number: usize) -> Result<usize, Error> {
+    if (1..=len).contains(&number) {
+        Ok(len - number)
+    } else {
+        Err(passmogu::Error::HistoryNotFound.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn number_entries() {
+        assert_eq!(entry_index(3, 1).unwrap(), 2);
+        assert_eq!(entry_index(3, 3).unwrap(), 0);
+        assert!(entry_index(3, 0).is_err());
+        assert!(entry_index(3, 4).is_err());
+        assert!(entry_index(0, 1).is_err());
+    }
+}
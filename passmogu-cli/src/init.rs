@@ -0,0 +1,40 @@
+use crate::error::Error;
+use crate::lock::VaultLock;
+use crate::session::prompt_new_password;
+use crate::storage::save_vault;
+use passmogu::{
+    encrypt::KdfParams,
+    header::Header,
+    vault::{EncryptedVault, Vault},
+};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Creates a new, empty vault at path, protected by a master password read twice from stdin.
+/// Refuses to replace an existing file unless force is set.
+/// Missing parent directories are created, only accessible to the current user.
+pub(crate) fn init_vault(path: &Path, force: bool, lock_timeout: Duration) -> Result<(), Error> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(dir)?;
+    }
+    let _lock = VaultLock::acquire(path, lock_timeout)?;
+    // check before asking for a password, save_vault would happily replace the file
+    if !force && fs::symlink_metadata(path).is_ok() {
+        return Err(Error::VaultExists(path.to_path_buf()));
+    }
+    let password = prompt_new_password()?;
+
+    let kdf = KdfParams::default();
+    let (header, key) = Header::new(kdf, password.expose())?;
+    let vault = Vault::new(header);
+    save_vault(path, &EncryptedVault::seal(&vault, key.expose())?)?;
+
+    println!("Created vault {}", fs::canonicalize(path)?.display());
+    println!("Key derivation: {kdf}");
+    Ok(())
+}
@@ -0,0 +1,657 @@
+use crate::error::Error;
+use passmogu::secret::Secret;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+/// What read_command got.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Input {
+    /// a line of this length, in the buffer
+    Line(usize),
+    /// the end of input (Ctrl-D on a terminal)
+    End,
+    /// no input for the idle timeout
+    Idle,
+}
+
+/// Candidates for the word being completed, given the tokens before it and the word, best first.
+pub(crate) type Complete<'a> = dyn Fn(&[&[u8]], &[u8]) -> Vec<Secret> + 'a;
+
+/// Prints prompt and reads a line into buffer like read_line, giving up with Input::Idle after
+/// idle_timeout without input (see wait_for_input).
+/// On a terminal the line is edited here rather than by the terminal, so Tab can complete the word
+/// at the end of the line with complete. Backspace and Ctrl-U erase, Ctrl-C discards the line and
+/// Ctrl-D ends input if the line is empty, other control keys are ignored.
+pub(crate) fn read_command(
+    prompt: &str,
+    buffer: &mut [u8],
+    idle_timeout: Duration,
+    complete: &Complete,
+) -> Result<Input, Error> {
+    print!("{prompt}");
+    io::stdout().flush()?;
+    #[cfg(unix)]
+    if let Some(raw) = RawMode::new() {
+        return raw.edit_line(prompt, buffer, idle_timeout, complete);
+    }
+    if !wait_for_input(idle_timeout)? {
+        return Ok(Input::Idle);
+    }
+    Ok(match read_line(&mut io::stdin().lock(), buffer)? {
+        Some(len) => Input::Line(len),
+        None => Input::End,
+    })
+}
+
+/// Reads one line from input into buffer, returning its length without the newline, or None at
+/// the end of input (Ctrl-D on a terminal). A last line without a newline is still returned.
+/// Reads byte by byte so nothing past the newline is consumed, and nothing but buffer ever
+/// holds the line. Lines longer than buffer fail with LineTooLong after skipping to the next line.
+pub(crate) fn read_line(input: &mut impl Read, buffer: &mut [u8]) -> Result<Option<usize>, Error> {
+    let mut byte = Secret::zero(1);
+    let mut len = 0;
+    let mut too_long = false;
+    loop {
+        if input.read(byte.expose_mut())? == 0 {
+            if len == 0 && !too_long {
+                return Ok(None);
+            }
+            break;
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        if len == buffer.len() {
+            too_long = true;
+            continue;
+        }
+        buffer[len] = byte[0];
+        len += 1;
+    }
+    if too_long {
+        buffer.fill(0);
+        return Err(Error::LineTooLong(buffer.len()));
+    }
+    if len > 0 && buffer[len - 1] == b'\r' {
+        len -= 1;
+    }
+    Ok(Some(len))
+}
+
+/// Waits until stdin has input or timeout passes, returning false on timeout. Only terminals are
+/// waited on, they hand out input a line at a time so nothing is left in stdin's buffer where poll
+/// can't see it. Other input and a zero timeout return true right away.
+#[cfg(unix)]
+pub(crate) fn wait_for_input(timeout: Duration) -> Result<bool, Error> {
+    // SAFETY: isatty only inspects the descriptor
+    if timeout.is_zero() || unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return Ok(true);
+    }
+    wait_readable(libc::STDIN_FILENO, timeout)
+}
+
+/// Waits until fd is readable or timeout passes, returning false on timeout.
+/// A zero timeout waits for as long as it takes.
+#[cfg(unix)]
+pub(crate) fn wait_readable(fd: std::os::fd::RawFd, timeout: Duration) -> Result<bool, Error> {
+    let millis = match timeout.as_millis() {
+        0 => -1,
+        millis => libc::c_int::try_from(millis).unwrap_or(libc::c_int::MAX),
+    };
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        // SAFETY: pollfd is a single valid pollfd
+        match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error().into()),
+            ready => return Ok(ready > 0),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn wait_for_input(_timeout: Duration) -> Result<bool, Error> {
+    Ok(true)
+}
+
+/// Turns off the terminal's line editing, echo and signal keys for as long as it's alive, so
+/// read_command can handle every key itself.
+/// The terminal is also restored if SIGINT, SIGTERM or SIGHUP end the process meanwhile, or
+/// SIGTSTP stops it. Raw mode comes back once a stopped process continues.
+#[cfg(unix)]
+struct RawMode {
+    original: libc::termios,
+    // handlers replaced by restore_terminal, indexed like SIGNALS
+    previous: [libc::sigaction; 4],
+}
+
+// Signals that would end or stop the process with the terminal still raw.
+#[cfg(unix)]
+const SIGNALS: [libc::c_int; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGTSTP];
+
+// Copies of RawMode's original and raw attributes for restore_terminal, which can't get at the
+// RawMode. Only written while restore_terminal isn't installed.
+#[cfg(unix)]
+static mut ORIGINAL: std::mem::MaybeUninit<libc::termios> = std::mem::MaybeUninit::uninit();
+#[cfg(unix)]
+static mut RAW: std::mem::MaybeUninit<libc::termios> = std::mem::MaybeUninit::uninit();
+
+/// Signal handler: restores the terminal, then lets the signal end or stop the process as it
+/// would have. If the process is stopped and continued, raw mode and the handler are put back.
+#[cfg(unix)]
+extern "C" fn restore_terminal(signal: libc::c_int) {
+    // SAFETY: ORIGINAL and RAW were initialized before this handler was installed, and
+    // tcsetattr, signal, sigemptyset, sigaddset, pthread_sigmask, raise and sigaction are
+    // async-signal-safe.
+    unsafe {
+        libc::tcsetattr(
+            libc::STDIN_FILENO,
+            libc::TCSANOW,
+            (&raw const ORIGINAL).cast::<libc::termios>(),
+        );
+        libc::signal(signal, libc::SIG_DFL);
+        // the signal is blocked while its handler runs, raise has to take effect right away
+        let mut unblock: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut unblock);
+        libc::sigaddset(&mut unblock, signal);
+        libc::pthread_sigmask(libc::SIG_UNBLOCK, &unblock, std::ptr::null_mut());
+        libc::raise(signal);
+        // only reached once a stopped process continues
+        install_restore(signal, std::ptr::null_mut());
+        libc::tcsetattr(
+            libc::STDIN_FILENO,
+            libc::TCSANOW,
+            (&raw const RAW).cast::<libc::termios>(),
+        );
+    }
+}
+
+/// Installs restore_terminal as the handler of signal, storing the one it replaces in previous
+/// unless that's null.
+/// # Safety
+/// previous must be null or valid for writes.
+#[cfg(unix)]
+unsafe fn install_restore(signal: libc::c_int, previous: *mut libc::sigaction) {
+    // SAFETY: sigaction is a plain C struct, all zeroes means no flags
+    unsafe {
+        let mut handler: libc::sigaction = std::mem::zeroed();
+        handler.sa_sigaction = restore_terminal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut handler.sa_mask);
+        libc::sigaction(signal, &handler, previous);
+    }
+}
+
+#[cfg(unix)]
+impl RawMode {
+    /// None if stdin isn't a terminal.
+    fn new() -> Option<Self> {
+        // SAFETY: termios is a plain C struct that tcgetattr fully initializes, stdin's file
+        // descriptor stays open for the whole process.
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return None;
+            }
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return None;
+            }
+            let original = termios;
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            (&raw mut ORIGINAL).write(std::mem::MaybeUninit::new(original));
+            (&raw mut RAW).write(std::mem::MaybeUninit::new(termios));
+            let mut previous: [libc::sigaction; 4] = std::mem::zeroed();
+            for (signal, previous) in SIGNALS.iter().zip(&mut previous) {
+                install_restore(*signal, previous);
+            }
+            let raw = RawMode { original, previous };
+            // dropping raw restores the handlers
+            // TCSANOW keeps whatever was typed ahead
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return None;
+            }
+            Some(raw)
+        }
+    }
+
+    fn edit_line(
+        &self,
+        prompt: &str,
+        buffer: &mut [u8],
+        idle_timeout: Duration,
+        complete: &Complete,
+    ) -> Result<Input, Error> {
+        let mut stdout = io::stdout();
+        let redraw = |stdout: &mut io::Stdout, line: &[u8]| -> io::Result<()> {
+            write!(stdout, "\r{prompt}")?;
+            stdout.write_all(line)?;
+            // clears the rest of the screen line
+            stdout.write_all(b"\x1b[K")
+        };
+        let mut len = 0;
+        let mut byte = Secret::zero(1);
+        // skipping the rest of an escape sequence, sent by arrow keys and such
+        let mut escape = Escape::None;
+        loop {
+            stdout.flush()?;
+            if !wait_readable(libc::STDIN_FILENO, idle_timeout)? {
+                buffer[..len].fill(0);
+                return Ok(Input::Idle);
+            }
+            // read directly, stdin's buffer could keep bytes where poll can't see them
+            // SAFETY: byte is a valid buffer of length 1
+            match unsafe {
+                libc::read(libc::STDIN_FILENO, byte.expose_mut().as_mut_ptr().cast(), 1)
+            } {
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                -1 => return Err(io::Error::last_os_error().into()),
+                0 if len == 0 => return Ok(Input::End),
+                0 => {
+                    stdout.write_all(b"\n")?;
+                    return Ok(Input::Line(len));
+                }
+                _ => (),
+            }
+            escape = match (escape, byte[0]) {
+                (Escape::None, 0x1B) => Escape::Start,
+                (Escape::None, _) => Escape::None,
+                (Escape::Start, b'[') => Escape::Csi,
+                (Escape::Start, b'O') => Escape::Ss3,
+                (Escape::Csi, 0x40..=0x7E) | (Escape::Start | Escape::Ss3, _) => {
+                    escape = Escape::None;
+                    continue;
+                }
+                (Escape::Csi, _) => continue,
+            };
+            if escape != Escape::None {
+                continue;
+            }
+            match byte[0] {
+                b'\r' | b'\n' => {
+                    stdout.write_all(b"\n")?;
+                    return Ok(Input::Line(len));
+                }
+                // Ctrl-D
+                0x04 if len == 0 => return Ok(Input::End),
+                // Ctrl-C
+                0x03 => {
+                    buffer[..len].fill(0);
+                    len = 0;
+                    stdout.write_all(b"^C\n")?;
+                    redraw(&mut stdout, &[])?;
+                }
+                // Ctrl-U
+                0x15 => {
+                    buffer[..len].fill(0);
+                    len = 0;
+                    redraw(&mut stdout, &[])?;
+                }
+                // Backspace, erases a whole UTF-8 character
+                0x7F | 0x08 => {
+                    while len > 0 {
+                        len -= 1;
+                        let erased = buffer[len];
+                        buffer[len] = 0;
+                        if erased & 0xC0 != 0x80 {
+                            stdout.write_all(b"\x08 \x08")?;
+                            break;
+                        }
+                    }
+                }
+                b'\t' => match complete_word(buffer, len, complete)? {
+                    Completion::Extended(extended) => {
+                        len = extended;
+                        redraw(&mut stdout, &buffer[..len])?;
+                    }
+                    Completion::Candidates(candidates) => {
+                        stdout.write_all(b"\n")?;
+                        for candidate in candidates.iter().take(MAX_CANDIDATES_SHOWN) {
+                            stdout.write_all(candidate.expose())?;
+                            stdout.write_all(b"  ")?;
+                        }
+                        if candidates.len() > MAX_CANDIDATES_SHOWN {
+                            write!(
+                                stdout,
+                                "and {} more",
+                                candidates.len() - MAX_CANDIDATES_SHOWN
+                            )?;
+                        }
+                        stdout.write_all(b"\n")?;
+                        redraw(&mut stdout, &buffer[..len])?;
+                    }
+                    Completion::None => stdout.write_all(b"\x07")?,
+                },
+                0x00..0x20 => (),
+                _ if len == buffer.len() => stdout.write_all(b"\x07")?,
+                typed => {
+                    buffer[len] = typed;
+                    len += 1;
+                    stdout.write_all(&[typed])?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the attributes tcgetattr returned in new, then the handlers sigaction
+        // returned there.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+            for (signal, previous) in SIGNALS.iter().zip(&self.previous) {
+                libc::sigaction(*signal, previous, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Escape {
+    None,
+    // after ESC
+    Start,
+    // ESC [ then parameters up to a final byte
+    Csi,
+    // ESC O then one byte
+    Ss3,
+}
+
+// Pressing Tab lists at most this many candidates.
+#[cfg(unix)]
+const MAX_CANDIDATES_SHOWN: usize = 30;
+
+/// What pressing Tab did.
+#[cfg(unix)]
+#[derive(Debug, PartialEq, Eq)]
+enum Completion {
+    /// nothing to complete
+    None,
+    /// the line now has this length
+    Extended(usize),
+    /// more than one candidate that can't be narrowed down by extending the word
+    Candidates(Vec<Secret>),
+}
+
+/// Completes the word at the end of the first len bytes of buffer with the candidates complete
+/// gives for it. A single candidate replaces the word, followed by a space. Several replace it
+/// with their longest common prefix if that's longer, or are returned to be listed.
+/// Words with quotes, and completions that don't fit in buffer, aren't completed.
+#[cfg(unix)]
+fn complete_word(buffer: &mut [u8], len: usize, complete: &Complete) -> Result<Completion, Error> {
+    let line = &buffer[..len];
+    // like tokenize, only to find where the last word starts
+    let mut start = 0;
+    let mut quote = None;
+    let mut i = 0;
+    while i < line.len() {
+        match (quote, line[i]) {
+            (None, b'\\') => i += 1,
+            (None, b'\'' | b'"') => quote = Some(line[i]),
+            (None, byte) if byte.is_ascii_whitespace() => start = i + 1,
+            (Some(b'"'), b'\\') => i += 1,
+            (Some(open), byte) if byte == open => quote = None,
+            _ => (),
+        }
+        i += 1;
+    }
+    let word = &line[start..];
+    if quote.is_some() || word.iter().any(|byte| matches!(byte, b'\'' | b'"')) {
+        return Ok(Completion::None);
+    }
+    let mut token_buffer = Secret::zero(len);
+    let (before, word_buffer) = token_buffer.expose_mut().split_at_mut(start);
+    let tokens = tokenize(&line[..start], before)?;
+    let word = tokenize(word, word_buffer)?
+        .first()
+        .copied()
+        .unwrap_or_default();
+    let candidates = complete(&tokens, word);
+    // candidates that start with word are completed before ones that only match it loosely
+    let starts_with_word = |candidate: &&Secret| {
+        let candidate = candidate.expose();
+        candidate.len() >= word.len() && candidate[..word.len()].eq_ignore_ascii_case(word)
+    };
+    let mut pool: Vec<&Secret> = candidates.iter().filter(starts_with_word).collect();
+    if pool.is_empty() {
+        pool = candidates.iter().collect();
+    }
+    let replacement = match pool.as_slice() {
+        [] => return Ok(Completion::None),
+        [only] => only.expose(),
+        [first, rest @ ..] => {
+            let common = rest.iter().fold(first.len(), |common, candidate| {
+                let same = first.expose().iter().zip(candidate.expose());
+                common.min(same.take_while(|(a, b)| a == b).count())
+            });
+            let common = &first.expose()[..common];
+            let extends = common.len() >= word.len()
+                && common[..word.len()].eq_ignore_ascii_case(word)
+                && common != word;
+            if !extends {
+                return Ok(Completion::Candidates(candidates));
+            }
+            common
+        }
+    };
+    let special = |byte: &u8| byte.is_ascii_whitespace() || matches!(byte, b'\'' | b'"' | b'\\');
+    let escaped_len = replacement.len() + replacement.iter().filter(|byte| special(byte)).count();
+    let space = usize::from(pool.len() == 1);
+    if start + escaped_len + space > buffer.len() {
+        return Ok(Completion::None);
+    }
+    buffer[start..len].fill(0);
+    let mut end = start;
+    for byte in replacement {
+        if special(byte) {
+            buffer[end] = b'\\';
+            end += 1;
+        }
+        buffer[end] = *byte;
+        end += 1;
+    }
+    if space == 1 {
+        buffer[end] = b' ';
+        end += 1;
+    }
+    Ok(Completion::Extended(end))
+}
+
+/// Asks a yes or no question on stderr like the password prompts, an empty answer (or the end
+/// of input) picks default.
+pub(crate) fn ask(question: &str, default: bool) -> Result<bool, Error> {
+    let choices = if default { "[Y/n]" } else { "[y/N]" };
+    eprint!("{question} {choices} ");
+    io::stderr().flush()?;
+    let mut buffer = [0; 16];
+    let len = read_line(&mut io::stdin().lock(), &mut buffer)?.unwrap_or(0);
+    Ok(match &buffer[..len] {
+        b"y" | b"Y" | b"yes" => true,
+        b"n" | b"N" | b"no" => false,
+        _ => default,
+    })
+}
+
+/// Splits line into tokens, roughly like a shell does: tokens are separated by whitespace,
+/// 'single quotes' keep everything up to the next single quote, "double quotes" keep everything
+/// but allow \" and \\ inside, and outside of quotes a backslash escapes the next byte.
+/// Quoted parts next to each other join into one token, so '' is an empty token.
+/// Unquoted tokens are written to out, which must be at least as long as line, so no unzeroized
+/// copies are made. The returned tokens borrow from out.
+pub(crate) fn tokenize<'a>(line: &[u8], out: &'a mut [u8]) -> Result<Vec<&'a [u8]>, Error> {
+    debug_assert!(out.len() >= line.len());
+    // (start, end) of each token in out
+    let mut bounds = Vec::new();
+    let mut write_head = 0;
+    let mut token_start = None;
+    let mut i = 0;
+    while i < line.len() {
+        let byte = line[i];
+        i += 1;
+        if byte.is_ascii_whitespace() {
+            if let Some(start) = token_start.take() {
+                bounds.push((start, write_head));
+            }
+            continue;
+        }
+        token_start.get_or_insert(write_head);
+        match byte {
+            b'\'' | b'"' => loop {
+                let Some(&quoted) = line.get(i) else {
+                    return Err(Error::UnterminatedQuote);
+                };
+                i += 1;
+                if quoted == byte {
+                    break;
+                }
+                let escapable = byte == b'"' && i < line.len() && matches!(line[i], b'"' | b'\\');
+                out[write_head] = if quoted == b'\\' && escapable {
+                    i += 1;
+                    line[i - 1]
+                } else {
+                    quoted
+                };
+                write_head += 1;
+            },
+            b'\\' if i < line.len() => {
+                out[write_head] = line[i];
+                write_head += 1;
+                i += 1;
+            }
+            _ => {
+                out[write_head] = byte;
+                write_head += 1;
+            }
+        }
+    }
+    if let Some(start) = token_start {
+        bounds.push((start, write_head));
+    }
+    let out = &*out;
+    Ok(bounds
+        .into_iter()
+        .map(|(start, end)| &out[start..end])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let mut out = vec![0; line.len()];
+        let tokens = tokenize(line, &mut out)?;
+        Ok(tokens.iter().map(|token| token.to_vec()).collect())
+    }
+
+    #[test]
+    fn read_lines() {
+        let mut input: &[u8] = b"ls\r\n\nget irc password\nq";
+        let mut buffer = [0; 16];
+        let mut lines = Vec::new();
+        while let Some(len) = read_line(&mut input, &mut buffer).unwrap() {
+            lines.push(buffer[..len].to_vec());
+        }
+        assert_eq!(lines, [&b"ls"[..], b"", b"get irc password", b"q"]);
+        // stays at the end
+        assert_eq!(read_line(&mut input, &mut buffer).unwrap(), None);
+    }
+
+    #[test]
+    fn reject_long_line() {
+        let mut input: &[u8] = b"0123456789abcdef\n0123456789abcdefg\nls\n0123456789abcdefg";
+        let mut buffer = [0; 16];
+        assert_eq!(read_line(&mut input, &mut buffer).unwrap(), Some(16));
+        assert!(matches!(
+            read_line(&mut input, &mut buffer),
+            Err(Error::LineTooLong(16))
+        ));
+        assert_eq!(buffer, [0; 16]);
+        // the rest of the long line was skipped
+        assert_eq!(read_line(&mut input, &mut buffer).unwrap(), Some(2));
+        assert_eq!(&buffer[..2], b"ls");
+        assert!(matches!(
+            read_line(&mut input, &mut buffer),
+            Err(Error::LineTooLong(16))
+        ));
+        assert_eq!(read_line(&mut input, &mut buffer).unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn complete_words() {
+        let complete = |tokens: &[&[u8]], word: &[u8]| -> Vec<Secret> {
+            let candidates: &[&[u8]] = match tokens {
+                [] => &[b"show", b"show!", b"set", b"switch"],
+                [b"show"] => &[b"my bank", b"my bar"],
+                _ => &[],
+            };
+            // loosely, like search::rank
+            candidates
+                .iter()
+                .filter(|candidate| {
+                    let mut bytes = candidate.iter();
+                    word.iter().all(|byte| bytes.any(|b| b == byte))
+                })
+                .map(|candidate| Secret::new((*candidate).into()))
+                .collect()
+        };
+        let completed = |line: &[u8], size: usize| {
+            let mut buffer = vec![0; size];
+            buffer[..line.len()].copy_from_slice(line);
+            match complete_word(&mut buffer, line.len(), &complete).unwrap() {
+                Completion::Extended(len) => Ok(buffer[..len].to_vec()),
+                Completion::Candidates(candidates) => Err(candidates.len()),
+                Completion::None => Err(0),
+            }
+        };
+        assert_eq!(completed(b"se", 16).unwrap(), b"set ");
+        assert_eq!(completed(b"s", 16), Err(4));
+        assert_eq!(completed(b"sw", 16).unwrap(), b"switch ");
+        assert_eq!(completed(b"sh", 16).unwrap(), b"show");
+        assert_eq!(completed(b"show", 16), Err(2));
+        assert_eq!(completed(b"show  m", 16).unwrap(), b"show  my\\ ba");
+        assert_eq!(
+            completed(b"show my\\ bank", 16).unwrap(),
+            b"show my\\ bank "
+        );
+        // quoted words, unknown words and completions that don't fit are left alone
+        assert_eq!(completed(b"show 'my", 16), Err(0));
+        assert_eq!(completed(b"x", 16), Err(0));
+        assert_eq!(completed(b"show my\\ bank", 13), Err(0));
+    }
+
+    #[test]
+    fn tokenize_quotes() {
+        let accept: [(&[u8], &[&[u8]]); 11] = [
+            (b"", &[]),
+            (b"  \t ", &[]),
+            (b"get irc password", &[b"get", b"irc", b"password"]),
+            (b"  ls  ", &[b"ls"]),
+            (
+                b"get bank \"What's your mother's maiden name?\"",
+                &[b"get", b"bank", b"What's your mother's maiden name?"],
+            ),
+            (b"set 'my bank' pin", &[b"set", b"my bank", b"pin"]),
+            (b"set my\\ bank pin", &[b"set", b"my bank", b"pin"]),
+            (b"'' \"\"", &[b"", b""]),
+            (b"a'b c'\"d\"e", &[b"ab cde"]),
+            (b"\"\\\"\\\\\\n\" '\\'", &[b"\"\\\\n", b"\\"]),
+            (b"trailing\\", &[b"trailing\\"]),
+        ];
+        for (line, expect) in accept {
+            let tokens = tokens(line).unwrap();
+            assert_eq!(tokens, expect, "{}", String::from_utf8_lossy(line));
+        }
+        for line in [&b"get 'irc"[..], b"\"", b"a\"b\\\""] {
+            assert!(matches!(tokens(line), Err(Error::UnterminatedQuote)));
+        }
+    }
+}
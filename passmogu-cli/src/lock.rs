@@ -0,0 +1,91 @@
+use crate::error::Error;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often a waiting process retries the lock.
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Exclusive advisory lock on a vault file, released when dropped (or when the process exits).
+/// The lock is taken on "vault.lock" next to the vault rather than on the vault itself, because
+/// saving renames a new file over the vault and a lock on the old file would go with it.
+pub(crate) struct VaultLock {
+    _file: File,
+}
+
+impl VaultLock {
+    /// Locks the vault at path, waiting up to timeout for another process to release it.
+    /// A zero timeout fails right away with Locked.
+    pub(crate) fn acquire(path: &Path, timeout: Duration) -> Result<Self, Error> {
+        let lock_path = crate::storage::sibling(path, ".lock");
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&lock_path)?;
+        let deadline = Instant::now() + timeout;
+        while !try_lock(&file)? {
+            if Instant::now() >= deadline {
+                return Err(Error::Locked(lock_path));
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+        Ok(VaultLock { _file: file })
+    }
+}
+
+/// Returns whether the lock was taken, false if another process holds it.
+#[cfg(unix)]
+fn try_lock(file: &File) -> Result<bool, Error> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: file owns a valid descriptor for the duration of the call.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.kind() == std::io::ErrorKind::WouldBlock {
+        return Ok(false);
+    }
+    Err(error.into())
+}
+
+/// Without flock, concurrent instances aren't protected from each other.
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> Result<bool, Error> {
+    Ok(true)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn exclusive_lock() {
+        let dir = std::env::temp_dir().join(format!("passmogu-{}-lock", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vault");
+
+        let lock = VaultLock::acquire(&path, Duration::ZERO).unwrap();
+        assert!(matches!(
+            VaultLock::acquire(&path, Duration::ZERO),
+            Err(Error::Locked(_))
+        ));
+        let start = Instant::now();
+        assert!(matches!(
+            VaultLock::acquire(&path, Duration::from_millis(200)),
+            Err(Error::Locked(_))
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // a waiting process gets the lock once it's released
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(lock);
+        });
+        VaultLock::acquire(&path, Duration::from_secs(10)).unwrap();
+        holder.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
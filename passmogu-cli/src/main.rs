@@ -1,27 +1,240 @@
+// supplies passmogu agent, and lets other commands use it
+#[cfg(unix)]
+mod agent;
+// copying answers to the system clipboard
+mod clipboard;
+// settings from config.toml
+mod config;
 mod error;
+// supplies passmogu history and passmogu restore
+mod history;
+// supplies passmogu init
+mod init;
+// reading and tokenizing session input
+mod input;
+// keeping concurrent passmogu processes from clobbering each other's writes
+mod lock;
+// supplies passmogu otp
+mod otp;
+// supplies passmogu passwd
+mod passwd;
+// supplies passmogu ls, passmogu find and passmogu get
+mod query;
+// named vaults, supplies passmogu vault
+mod registry;
 // supplies passmogu unlock
 mod session;
+// reading and writing vault files
+mod storage;
+// supplies passmogu store
+mod store;
+// shared fixtures for unit tests
+#[cfg(test)]
+mod test_util;
 
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 struct Args {
     #[command(subcommand)]
     command: Command,
+    /// Name or path of the vault, defaults to the one in the config file, the default named vault
+    /// or $XDG_DATA_HOME/passmogu/vault
+    #[arg(long, global = true, env = "PASSMOGU_VAULT")]
+    vault: Option<PathBuf>,
+    /// Path to the config file, defaults to $XDG_CONFIG_HOME/passmogu/config.toml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Seconds to wait for another passmogu process to finish writing the vault, 0 fails at once
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 0)]
+    wait: u64,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
-    Init,
+    /// Create a new, empty vault
+    Init {
+        /// Overwrite the vault file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
     Unlock,
-    Store,
+    /// List the forms in the vault
+    Ls {
+        #[command(flatten)]
+        filter: query::Filter,
+    },
+    /// Search forms by name, field prompt, URL, tag and folder, best matches first
+    Find {
+        /// Matched ignoring case, as a substring or with other letters in between
+        query: String,
+    },
+    /// Print the answer to one of a form's prompts
+    Get {
+        form: String,
+        /// Defaults to the form's password field
+        prompt: Option<String>,
+    },
+    /// Print a one-time code from a field holding an otpauth:// URI, HOTP counters are saved
+    Otp {
+        form: String,
+        /// Prompt of the field, defaults to the form's first otpauth:// field
+        prompt: Option<String>,
+    },
+    /// Add or update a form without entering the interactive session
+    Store {
+        /// Name of the form, e.g. the website it's for
+        form: String,
+        /// Field to store, ANSWER can be @generate or @generate:LENGTH for a generated password,
+        /// @- to read it from stdin, or start with @@ for a literal @
+        #[arg(long = "field", value_name = "PROMPT=ANSWER")]
+        fields: Vec<String>,
+        /// Replace the form if it already exists
+        #[arg(long, conflicts_with = "merge_fields")]
+        replace: bool,
+        /// Merge fields into the form if it already exists, overwriting fields with the same prompt
+        #[arg(long)]
+        merge_fields: bool,
+    },
+    /// List the previous answers of a form's fields, newest first
+    History {
+        form: String,
+        /// Print the answers too
+        #[arg(long)]
+        reveal: bool,
+    },
+    /// Put a previous answer back into a form
+    Restore {
+        form: String,
+        /// Number of the entry as listed by passmogu history, 1 is the newest
+        entry: usize,
+    },
+    /// Replace the vault file with one of its backups
+    RestoreBackup {
+        /// Backup to restore, 1 is the newest. Defaults to the newest one that isn't corrupt
+        #[arg(long)]
+        generation: Option<usize>,
+    },
+    /// Change the master password
+    Passwd {
+        /// Also replace the vault's data key and re-encrypt every field
+        #[arg(long)]
+        rotate: bool,
+    },
+    /// Unlock the vault once and keep its key in memory, so ls, get and store don't ask for the
+    /// master password while it runs. Locks after the configured auto_lock_timeout without use
+    #[cfg(unix)]
+    Agent,
+    /// Manage named vaults
+    Vault {
+        #[command(subcommand)]
+        command: VaultCommand,
+    },
 }
 
-fn main() -> Result<(), error::Error> {
+#[derive(Subcommand, Debug)]
+enum VaultCommand {
+    /// Give the vault file at path a name, the first vault added becomes the default
+    Add { name: String, path: PathBuf },
+    /// List named vaults, * marks the default
+    List,
+    /// Make a named vault the default
+    Use { name: String },
+    /// Forget a name, the vault file is kept
+    Remove { name: String },
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("passmogu: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), error::Error> {
+    let config = config::Config::load(args.config.as_deref())?;
+    let mut registry = registry::Registry::load()?;
+    if let Command::Vault { command } = args.command {
+        return manage_vaults(command, &mut registry);
+    }
+    let vault = config.vault_path(args.vault.as_deref(), &registry)?;
+    let lock_timeout = Duration::from_secs(args.wait);
     match args.command {
-        Command::Init => todo!(),
-        Command::Unlock => session::session_repl(),
-        Command::Store => todo!(),
+        Command::Init { force } => init::init_vault(&vault, force, lock_timeout),
+        Command::Unlock => session::session_repl(&vault, lock_timeout, &config, &registry),
+        Command::Ls { filter } => query::list_forms(&vault, &filter, lock_timeout),
+        Command::Find { query } => query::find_forms(&vault, &query, lock_timeout),
+        Command::Get { form, prompt } => {
+            query::print_answer(&vault, &form, prompt.as_deref(), lock_timeout)
+        }
+        Command::Otp { form, prompt } => {
+            otp::print_code(&vault, &form, prompt.as_deref(), lock_timeout)
+        }
+        Command::Store {
+            form,
+            fields,
+            replace,
+            merge_fields,
+        } => {
+            let existing = match (replace, merge_fields) {
+                (true, _) => store::Existing::Replace,
+                (_, true) => store::Existing::Merge,
+                _ => store::Existing::Keep,
+            };
+            store::store_form(&vault, &form, &fields, existing, lock_timeout, &config)
+        }
+        Command::RestoreBackup { generation } => {
+            let _lock = lock::VaultLock::acquire(&vault, lock_timeout)?;
+            let backup = storage::restore_backup(&vault, generation)?;
+            println!("Restored {} from {}", vault.display(), backup.display());
+            Ok(())
+        }
+        Command::Passwd { rotate } => passwd::change_password(&vault, rotate, lock_timeout),
+        #[cfg(unix)]
+        Command::Agent => agent::run_agent(&vault, &config, lock_timeout),
+        Command::History { form, reveal } => {
+            history::print_history(&vault, &form, reveal, lock_timeout)
+        }
+        Command::Restore { form, entry } => {
+            history::restore_entry(&vault, &form, entry, lock_timeout, config.history_limit)
+        }
+        Command::Vault { .. } => unreachable!("handled before picking a vault"),
+    }
+}
+
+fn manage_vaults(
+    command: VaultCommand,
+    registry: &mut registry::Registry,
+) -> Result<(), error::Error> {
+    match command {
+        VaultCommand::Add { name, path } => {
+            registry.add(&name, &path)?;
+            registry.save()?;
+            println!("Added vault {name}");
+        }
+        VaultCommand::List => {
+            for (name, path, is_default) in registry.iter() {
+                let marker = if is_default { '*' } else { ' ' };
+                println!("{marker} {name}\t{}", path.display());
+            }
+        }
+        VaultCommand::Use { name } => {
+            registry.set_default(&name)?;
+            registry.save()?;
+            println!("Default vault is now {name}");
+        }
+        VaultCommand::Remove { name } => {
+            let path = registry.remove(&name)?;
+            registry.save()?;
+            println!("Removed vault {name}, {} was kept", path.display());
+        }
     }
+    Ok(())
 }
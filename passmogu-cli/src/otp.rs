@@ -0,0 +1,46 @@
+#[cfg(unix)]
+use crate::agent;
+use crate::error::Error;
+use crate::lock::VaultLock;
+use crate::storage::{save_vault, unlock_locked_vault};
+use passmogu::otp::{Code, next_code};
+use passmogu::vault::EncryptedVault;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Prints the current one-time code of form, made from its field with prompt or else its first
+/// field holding an otpauth:// URI. A HOTP counter is advanced and saved.
+/// Goes through the agent if it's running for this vault, otherwise asks for the master password.
+pub(crate) fn print_code(
+    path: &Path,
+    form: &str,
+    prompt: Option<&str>,
+    lock_timeout: Duration,
+) -> Result<(), Error> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let prompt = prompt.map(str::as_bytes);
+    #[cfg(unix)]
+    if let Some(mut agent) = agent::Client::connect(path)? {
+        show(&agent.otp(form.as_bytes(), prompt, time)?);
+        return Ok(());
+    }
+    let lock = VaultLock::acquire(path, lock_timeout)?;
+    let (_, mut vault, key) = unlock_locked_vault(path, &lock)?;
+    let code = next_code(&mut vault, key.expose(), form.as_bytes(), prompt, time)?;
+    if code.counter.is_some() {
+        save_vault(path, &EncryptedVault::seal(&vault, key.expose())?)?;
+    }
+    show(&code);
+    Ok(())
+}
+
+fn show(code: &Code) {
+    match (code.remaining, code.counter) {
+        (Some(remaining), _) => println!("{} ({remaining} seconds left)", code.code),
+        (_, Some(counter)) => println!("{} (counter {counter})", code.code),
+        _ => println!("{}", code.code),
+    }
+}
@@ -0,0 +1,32 @@
+use crate::error::Error;
+use crate::lock::VaultLock;
+use crate::session::prompt_new_password;
+use crate::storage::{load_locked_vault, save_vault};
+use passmogu::prompt;
+use std::path::Path;
+use std::time::Duration;
+
+/// Changes the master password of the vault at path.
+/// With rotate, the data key is replaced too and every field is re-encrypted.
+pub(crate) fn change_password(
+    path: &Path,
+    rotate: bool,
+    lock_timeout: Duration,
+) -> Result<(), Error> {
+    let lock = VaultLock::acquire(path, lock_timeout)?;
+    let vault = load_locked_vault(path, &lock)?;
+    let old_password = prompt::password("Current master password: ")?;
+    // check before asking for the new password twice
+    vault.unlock(old_password.expose())?;
+    let new_password = prompt_new_password()?;
+
+    let kdf = vault.header().kdf;
+    let rekeyed = if rotate {
+        vault.rotate_key(old_password.expose(), new_password.expose(), kdf)
+    } else {
+        vault.change_password(old_password.expose(), new_password.expose(), kdf)
+    };
+    save_vault(path, &rekeyed?)?;
+    println!("Master password changed");
+    Ok(())
+}
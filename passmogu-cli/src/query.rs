@@ -0,0 +1,113 @@
+#[cfg(unix)]
+use crate::agent;
+use crate::error::Error;
+use crate::session::{print_secret, select_field};
+use crate::storage::unlock_vault;
+use passmogu::search::search;
+use passmogu::vault::{FormMeta, Vault};
+use std::path::Path;
+use std::time::Duration;
+
+/// Which forms to list, by their metadata. The default lists every form.
+#[derive(clap::Args, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Filter {
+    /// Only list forms with this tag
+    #[arg(long)]
+    pub(crate) tag: Option<String>,
+    /// Only list forms in this folder or its subfolders
+    #[arg(long)]
+    pub(crate) folder: Option<String>,
+    /// Only list favorite forms
+    #[arg(long)]
+    pub(crate) favorites: bool,
+}
+
+impl Filter {
+    pub(crate) fn matches(&self, meta: &FormMeta) -> bool {
+        self.tag
+            .as_ref()
+            .is_none_or(|tag| meta.has_tag(tag.as_bytes()))
+            && self
+                .folder
+                .as_ref()
+                .is_none_or(|folder| meta.in_folder(folder.as_bytes()))
+            && (!self.favorites || meta.favorite)
+    }
+
+    /// Names of the matching forms in vault, sorted.
+    pub(crate) fn names<'a>(&self, vault: &'a Vault) -> Vec<&'a [u8]> {
+        let mut names: Vec<&[u8]> = vault
+            .form_names()
+            .filter(|name| vault.meta(name).is_some_and(|meta| self.matches(meta)))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Prints the names of the forms in the vault at path that match filter, sorted.
+/// Goes through the agent if it's running for this vault, otherwise asks for the master password.
+/// A corrupt vault file may be restored from a backup, see unlock_vault.
+pub(crate) fn list_forms(
+    path: &Path,
+    filter: &Filter,
+    lock_timeout: Duration,
+) -> Result<(), Error> {
+    #[cfg(unix)]
+    if let Some(mut agent) = agent::Client::connect(path)? {
+        for name in agent.list(filter)? {
+            println!("{}", String::from_utf8_lossy(name.expose()));
+        }
+        return Ok(());
+    }
+    let (_, vault, _) = unlock_vault(path, lock_timeout)?;
+    for name in filter.names(&vault) {
+        println!("{}", String::from_utf8_lossy(name));
+    }
+    Ok(())
+}
+
+/// Prints the forms in the vault at path that match query, best first, see passmogu::search.
+/// Goes through the agent like list_forms.
+pub(crate) fn find_forms(path: &Path, query: &str, lock_timeout: Duration) -> Result<(), Error> {
+    #[cfg(unix)]
+    if let Some(mut agent) = agent::Client::connect(path)? {
+        for (name, matched) in agent.find(query.as_bytes())? {
+            print_hit(name.expose(), &matched);
+        }
+        return Ok(());
+    }
+    let (_, vault, key) = unlock_vault(path, lock_timeout)?;
+    for hit in search(&vault, key.expose(), query.as_bytes())? {
+        print_hit(hit.name, hit.matched.name());
+    }
+    Ok(())
+}
+
+/// Prints a form found by find_forms, along with what matched unless it was the name.
+pub(crate) fn print_hit(name: &[u8], matched: &str) {
+    let name = String::from_utf8_lossy(name);
+    match matched {
+        "name" => println!("{name}"),
+        _ => println!("{name}  ({matched})"),
+    }
+}
+
+/// Prints the answer to prompt in form, or its password if prompt is None (see select_field).
+/// Goes through the agent like list_forms.
+pub(crate) fn print_answer(
+    path: &Path,
+    form: &str,
+    prompt: Option<&str>,
+    lock_timeout: Duration,
+) -> Result<(), Error> {
+    let prompt = prompt.map(str::as_bytes);
+    #[cfg(unix)]
+    if let Some(mut agent) = agent::Client::connect(path)? {
+        let fields = agent.get(form.as_bytes())?;
+        return print_secret(&select_field(&fields, prompt)?.answer);
+    }
+    let (_, vault, key) = unlock_vault(path, lock_timeout)?;
+    let fields = vault.get_and_open(form.as_bytes(), key.expose())?;
+    print_secret(&select_field(&fields, prompt)?.answer)
+}
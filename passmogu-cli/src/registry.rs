@@ -0,0 +1,175 @@
+use crate::config::xdg_dir;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{self, Path, PathBuf};
+
+/// Named vaults, kept in vaults.toml next to the config file and managed with passmogu vault.
+/// ```toml
+/// default = "personal"
+///
+/// [vaults]
+/// personal = "/home/me/.local/share/passmogu/vault"
+/// work = "/home/me/work/passmogu.vault"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Registry {
+    /// vault used when none is given
+    default: Option<String>,
+    /// absolute path of each vault by name
+    vaults: BTreeMap<String, PathBuf>,
+}
+
+impl Registry {
+    /// Reads the registry at default_registry_path, a missing file is an empty registry.
+    pub(crate) fn load() -> Result<Registry, Error> {
+        let Some(path) = default_registry_path() else {
+            return Ok(Registry::default());
+        };
+        match fs::read_to_string(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Registry::default()),
+            Err(e) => Err(Error::Config(path, e.to_string())),
+            Ok(text) => toml::from_str(&text).map_err(|e| Error::Config(path, e.message().into())),
+        }
+    }
+
+    /// Writes the registry to default_registry_path, creating its directory if needed.
+    pub(crate) fn save(&self) -> Result<(), Error> {
+        let path = default_registry_path().ok_or(Error::NoVaultPath)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self).expect("registry is representable in TOML");
+        fs::write(&path, text)?;
+        Ok(())
+    }
+
+    /// Registers the vault at path under name, it becomes the default if there's none yet.
+    /// Names can't contain path separators, so --vault can tell them apart from paths.
+    pub(crate) fn add(&mut self, name: &str, path: &Path) -> Result<(), Error> {
+        if name.is_empty() || name.contains(['/', path::MAIN_SEPARATOR]) {
+            return Err(Error::InvalidVaultName(name.into()));
+        }
+        if self.vaults.contains_key(name) {
+            return Err(Error::VaultNameTaken(name.into()));
+        }
+        self.vaults.insert(name.into(), path::absolute(path)?);
+        self.default.get_or_insert_with(|| name.into());
+        Ok(())
+    }
+
+    /// Unregisters name and returns its path, the vault file itself is left alone.
+    pub(crate) fn remove(&mut self, name: &str) -> Result<PathBuf, Error> {
+        let path = self
+            .vaults
+            .remove(name)
+            .ok_or_else(|| Error::UnknownVault(name.into()))?;
+        if self.default.as_deref() == Some(name) {
+            self.default = None;
+        }
+        Ok(path)
+    }
+
+    /// Makes name the vault used when none is given.
+    pub(crate) fn set_default(&mut self, name: &str) -> Result<(), Error> {
+        if !self.vaults.contains_key(name) {
+            return Err(Error::UnknownVault(name.into()));
+        }
+        self.default = Some(name.into());
+        Ok(())
+    }
+
+    /// Path of the default vault, if one is set.
+    pub(crate) fn default_path(&self) -> Option<&Path> {
+        self.vaults
+            .get(self.default.as_ref()?)
+            .map(PathBuf::as_path)
+    }
+
+    /// Turns a --vault argument into a path: a registered name, or else the argument as is if it
+    /// looks like a path (contains a path separator) or names an existing file.
+    /// Fails with Error::UnknownVault for anything else, most likely a mistyped name.
+    pub(crate) fn resolve(&self, vault: &Path) -> Result<PathBuf, Error> {
+        let name = vault.to_string_lossy();
+        if let Some(path) = self.vaults.get(&*name) {
+            return Ok(path.clone());
+        }
+        if name.contains(['/', path::MAIN_SEPARATOR]) || vault.exists() {
+            return Ok(vault.to_path_buf());
+        }
+        Err(Error::UnknownVault(name.into()))
+    }
+
+    /// The registered name of the vault at path, if any.
+    pub(crate) fn name_of(&self, path: &Path) -> Option<&str> {
+        let path = path::absolute(path).ok()?;
+        self.vaults
+            .iter()
+            .find(|(_, registered)| **registered == path)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Names and paths in order of name, along with whether each is the default.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &Path, bool)> {
+        self.vaults.iter().map(|(name, path)| {
+            let is_default = self.default.as_ref() == Some(name);
+            (name.as_str(), path.as_path(), is_default)
+        })
+    }
+}
+
+/// $XDG_CONFIG_HOME/passmogu/vaults.toml
+fn default_registry_path() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("passmogu").join("vaults.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manage_vaults() {
+        let mut registry = Registry::default();
+        assert_eq!(registry.default_path(), None);
+        registry.add("personal", Path::new("/p/vault")).unwrap();
+        registry.add("work", Path::new("/w/vault")).unwrap();
+        assert!(matches!(
+            registry.add("work", Path::new("/x")),
+            Err(Error::VaultNameTaken(_))
+        ));
+        assert!(matches!(
+            registry.add("a/b", Path::new("/x")),
+            Err(Error::InvalidVaultName(_))
+        ));
+        // the first vault added becomes the default
+        assert_eq!(registry.default_path(), Some(Path::new("/p/vault")));
+        registry.set_default("work").unwrap();
+        assert_eq!(registry.default_path(), Some(Path::new("/w/vault")));
+        assert!(matches!(
+            registry.set_default("shared"),
+            Err(Error::UnknownVault(_))
+        ));
+
+        let resolve = |vault| registry.resolve(Path::new(vault));
+        assert_eq!(resolve("work").unwrap(), Path::new("/w/vault"));
+        assert_eq!(resolve("./work").unwrap(), Path::new("./work"));
+        assert_eq!(resolve("/x/work").unwrap(), Path::new("/x/work"));
+        // an existing file needs no separator
+        assert_eq!(resolve("Cargo.toml").unwrap(), Path::new("Cargo.toml"));
+        assert!(matches!(resolve("wrok"), Err(Error::UnknownVault(_))));
+        assert_eq!(registry.name_of(Path::new("/p/vault")), Some("personal"));
+
+        let text = toml::to_string(&registry).unwrap();
+        assert_eq!(toml::from_str::<Registry>(&text).unwrap(), registry);
+
+        assert_eq!(registry.remove("work").unwrap(), Path::new("/w/vault"));
+        assert_eq!(registry.default_path(), None);
+        assert!(matches!(
+            registry.remove("work"),
+            Err(Error::UnknownVault(_))
+        ));
+    }
+}
@@ -1,65 +1,965 @@
+use crate::clipboard;
+use crate::config::Config;
 use crate::error::Error;
-use passmogu::{encrypt::derive_key, secret::Secret};
-use std::io::{self, Read, Write};
+use crate::history::{entry_index, print_entries};
+use crate::input::{self, Input};
+use crate::lock::VaultLock;
+use crate::query::{Filter, print_hit};
+use crate::registry::Registry;
+use crate::storage::{changed_on_disk, save_vault, unlock_vault};
+use passmogu::{
+    generate::MAX_LENGTH,
+    prompt,
+    search::{rank, search},
+    secret::Secret,
+    vault::{EncryptedVault, Field, FieldKind, FormMeta, Vault, open_form, seal_form},
+};
+use std::io::{self, Write};
+use std::path::{self, Path, PathBuf};
+use std::time::Duration;
 
-const WELCOME_MSG: &str = "Enter your master password to unlock vault: ";
+pub(crate) const WELCOME_MSG: &str = "Enter your master password to unlock vault: ";
+
+const HELP_MSG: &str = "\
+Commands:
+  ls                              list forms
+  ls tag|folder <name>            list forms with a tag, or in a folder or its subfolders
+  ls favorites                    list favorite forms
+  find <query>                    search names, prompts, URLs, tags and folders, best first
+  info <form>                     show a form's timestamps, folder and tags
+  tag <form> <tag>                add a tag to a form
+  untag <form> <tag>              remove a tag from a form
+  folder <form> [folder]          move a form into a folder, or out of any
+  fav <form>, unfav <form>        mark or unmark a form as a favorite
+  show <form>                     list a form's fields, hidden answers masked
+  show! <form>                    list a form's fields with every answer
+  get <form> [prompt]             print an answer, the password if no prompt is given
+  clip <form> [prompt]            copy an answer to the clipboard for a while
+  set <form> <prompt> [answer]    add or overwrite a field, asks for the answer if left out
+  kind <form> <prompt> <kind>     set what a field is: login, password, url, email, note,
+                                  otp, card, hidden, visible or none
+  rm <form> [prompt]              delete a form, or one of its fields
+  gen [length]                    print a generated password
+  gen <form> <prompt> [length]    store a generated password in a field
+  rename <form> <new name>        rename a form
+  history <form>                  list previous answers of a form's fields, newest first
+  history! <form>                 list previous answers, revealing them
+  restore <form> <n>              put the n-th answer listed by history back
+  open <vault>                    unlock another vault, by name or path, and switch to it
+  vaults                          list unlocked vaults, * marks the current one
+  switch <vault>                  make another unlocked vault the current one
+  cp <form> <vault> [new name]    copy a form into another unlocked vault
+  save                            write changes to the vault files
+  save!                           write changes even if a vault file changed since unlocking
+  q[uit]                          save if you want to and lock the vaults";
+
+// Every session command, and whether its first argument is a form. Tab completes the first word
+// of a line with these, and interpret knows them all.
+const COMMANDS: [(&[u8], bool); 28] = [
+    (b"help", false),
+    (b"ls", false),
+    (b"find", false),
+    (b"info", true),
+    (b"tag", true),
+    (b"untag", true),
+    (b"folder", true),
+    (b"fav", true),
+    (b"unfav", true),
+    (b"show", true),
+    (b"show!", true),
+    (b"get", true),
+    (b"clip", true),
+    (b"set", true),
+    (b"kind", true),
+    (b"rm", true),
+    (b"gen", true),
+    (b"rename", true),
+    (b"history", true),
+    (b"history!", true),
+    (b"restore", true),
+    (b"open", false),
+    (b"vaults", false),
+    (b"switch", false),
+    (b"cp", true),
+    (b"save", false),
+    (b"save!", false),
+    (b"quit", false),
+];
 
 // A value larger than any realistic input.
 // Would probably take a full 2 mins of typing full speed to exceed.
 const MAX_INPUT_LINE_LEN: usize = 1024;
 
-pub(crate) fn session_repl() -> Result<(), Error> {
-    unlock_vault()?;
+/// Vaults aren't locked while the session is idle, only while saving, so other commands can
+/// write them in the meantime. Saving refuses to overwrite such changes unless forced.
+/// After the config's auto_lock_timeout without input the keys are forgotten until the master
+/// passwords are entered again.
+pub(crate) fn session_repl(
+    path: &Path,
+    lock_timeout: Duration,
+    config: &Config,
+    registry: &Registry,
+) -> Result<(), Error> {
+    let first = Unlocked::unlock(path, lock_timeout, registry, config)?;
+    let mut session = Session {
+        lock_timeout,
+        config,
+        registry,
+        vaults: vec![first],
+        current: 0,
+    };
+    println!("Vault unlocked, enter help for a list of commands");
     let mut input_buffer = Secret::zero(MAX_INPUT_LINE_LEN);
+    let mut token_buffer = Secret::zero(MAX_INPUT_LINE_LEN);
     loop {
-        // io::stdin().read_line(input_buffer.expose_mut())?;
-        read_line(input_buffer.expose_mut())?;
-        // let tokens: Vec<&str> = input_buffer.split_whitespace().collect();
-        let tokens: Vec<&[u8]> = tokenize(input_buffer.expose());
-        if interpret(&tokens)? {
-            println!("Quitting, locking vault");
-            break;
-        }
+        let prompt = match session.vaults.as_slice() {
+            [_] => String::from("> "),
+            _ => format!("{}> ", session.current().label),
+        };
+        let line = input::read_command(
+            &prompt,
+            input_buffer.expose_mut(),
+            config.auto_lock_timeout(),
+            &|tokens, word| session.complete(tokens, word),
+        );
+        let result = match line {
+            Ok(Input::Idle) => {
+                session.relock()?;
+                continue;
+            }
+            Ok(Input::Line(len)) => {
+                input::tokenize(&input_buffer[..len], token_buffer.expose_mut())
+                    .and_then(|tokens| session.interpret(&tokens))
+            }
+            // Ctrl-D, a failed save is fatal since stdin is closed
+            Ok(Input::End) => {
+                println!();
+                session.quit()?;
+                Ok(true)
+            }
+            Err(e @ Error::LineTooLong(_)) => Err(e),
+            Err(e) => return Err(e),
+        };
         input_buffer.zeroize();
+        token_buffer.zeroize();
+        match result {
+            Ok(true) => {
+                println!("Quitting, locking vault");
+                break;
+            }
+            Ok(false) => (),
+            // a failed command leaves the session usable
+            Err(e) => println!("{e}"),
+        }
     }
-    Ok(())
+    clipboard::clear_pending()
+}
+
+/// The unlocked vaults, commands work on the current one.
+struct Session<'a> {
+    // how long to wait for another process to unlock a vault file
+    lock_timeout: Duration,
+    config: &'a Config,
+    registry: &'a Registry,
+    // never empty
+    vaults: Vec<Unlocked>,
+    // index into vaults
+    current: usize,
+}
+
+/// An unlocked vault along with everything needed to write it back.
+struct Unlocked {
+    // registered name, or the path if it has none
+    label: String,
+    path: PathBuf,
+    vault: Vault,
+    key: Secret,
+    // vault file as last loaded or saved, to notice other processes writing it
+    on_disk: EncryptedVault,
+    // whether vault differs from the vault file
+    unsaved: bool,
 }
 
-fn read_line(buffer: &mut [u8]) -> Result<(), Error> {
-    // unbuffered bytes here to avoid littering a buffer with a secret in it
-    // TODO: revise if performance becomes an issue
-    #[expect(clippy::unbuffered_bytes)]
-    for (i, byte) in io::stdin().bytes().enumerate() {
-        buffer[i] = byte?;
-        if buffer[i] == b'\n' {
-            buffer[i] = b' '; // allows .split to truncate nulls from end
-            break;
+impl Session<'_> {
+    /// Candidates for Tab completing word after tokens, best first: commands, then forms, prompts,
+    /// tags, folders, kinds or vaults depending on the command and argument.
+    fn complete(&self, tokens: &[&[u8]], word: &[u8]) -> Vec<Secret> {
+        let current = self.current();
+        let vault = &current.vault;
+        let metas = || vault.form_names().filter_map(|name| vault.meta(name));
+        let candidates: Vec<&[u8]> = match tokens {
+            [] => rank(COMMANDS.iter().map(|(name, _)| *name), word),
+            [b"ls"] => rank([&b"tag"[..], b"folder", b"favorites"], word),
+            [b"ls", b"tag"] | [b"tag" | b"untag", _] => rank(
+                unique(metas().flat_map(|meta| &meta.tags).map(|tag| &**tag)),
+                word,
+            ),
+            [b"ls", b"folder"] | [b"folder", _] => rank(
+                unique(metas().filter_map(|meta| meta.folder.as_deref())),
+                word,
+            ),
+            [b"open"] => rank(
+                self.registry.iter().map(|(name, _, _)| name.as_bytes()),
+                word,
+            ),
+            [b"switch"] | [b"cp", _] => {
+                rank(self.vaults.iter().map(|vault| vault.label.as_bytes()), word)
+            }
+            [b"kind", _, _] => rank(
+                FieldKind::ALL
+                    .iter()
+                    .map(|kind| kind.name().as_bytes())
+                    .chain([&b"none"[..]]),
+                word,
+            ),
+            [b"get" | b"clip" | b"set" | b"kind" | b"rm" | b"gen", form] => {
+                // prompts are only decrypted in memory, like for show
+                let Ok(fields) = current.open(form) else {
+                    return Vec::new();
+                };
+                let prompts = fields.iter().map(|field| field.prompt.expose());
+                return rank(prompts, word)
+                    .into_iter()
+                    .map(|prompt| Secret::new(prompt.into()))
+                    .collect();
+            }
+            [command] if COMMANDS.contains(&(command, true)) => rank(vault.form_names(), word),
+            _ => Vec::new(),
+        };
+        candidates
+            .into_iter()
+            .map(|candidate| Secret::new(candidate.into()))
+            .collect()
+    }
+
+    /// Returns whether main loop should quit
+    fn interpret(&mut self, tokens: &[&[u8]]) -> Result<bool, Error> {
+        match tokens {
+            [] => (),
+            [b"help" | b"h"] => println!("{HELP_MSG}"),
+            [b"ls"] => self.list(&Filter::default()),
+            [b"ls", b"tag", tag] => self.list(&Filter {
+                tag: Some(String::from_utf8_lossy(tag).into()),
+                ..Filter::default()
+            }),
+            [b"ls", b"folder", folder] => self.list(&Filter {
+                folder: Some(String::from_utf8_lossy(folder).into()),
+                ..Filter::default()
+            }),
+            [b"ls", b"favorites"] => self.list(&Filter {
+                favorites: true,
+                ..Filter::default()
+            }),
+            [b"find", query] => self.find(query)?,
+            [b"info", form] => self.info(form)?,
+            [b"tag", form, tag] => self.edit_meta(form, |meta| {
+                if !meta.add_tag(tag) {
+                    println!("Already tagged {}", String::from_utf8_lossy(tag));
+                }
+            })?,
+            [b"untag", form, tag] => self.edit_meta(form, |meta| {
+                if !meta.remove_tag(tag) {
+                    println!("Not tagged {}", String::from_utf8_lossy(tag));
+                }
+            })?,
+            [b"folder", form] => self.edit_meta(form, |meta| meta.folder = None)?,
+            [b"folder", form, folder] => {
+                self.edit_meta(form, |meta| meta.folder = Some(Box::from(*folder)))?
+            }
+            [b"fav", form] => self.edit_meta(form, |meta| meta.favorite = true)?,
+            [b"unfav", form] => self.edit_meta(form, |meta| meta.favorite = false)?,
+            [b"show", form] => self.show(form, false)?,
+            [b"show!", form] => self.show(form, true)?,
+            [b"get", form] => self.get(form, None)?,
+            [b"get", form, prompt] => self.get(form, Some(prompt))?,
+            [b"clip", form] => self.clip(form, None)?,
+            [b"clip", form, prompt] => self.clip(form, Some(prompt))?,
+            [b"set", form, prompt] => {
+                let answer = prompt::password("Answer: ")?;
+                let kind = FieldKind::guess(prompt, answer.expose());
+                self.set(form, prompt, answer, kind)?;
+            }
+            [b"set", form, prompt, answer] => {
+                let kind = FieldKind::guess(prompt, answer);
+                self.set(form, prompt, Secret::new(Box::from(*answer)), kind)?
+            }
+            [b"kind", form, prompt, kind] => self.set_kind(form, prompt, kind)?,
+            [b"rm", form] => self.remove(form)?,
+            [b"rm", form, prompt] => self.remove_field(form, prompt)?,
+            [b"gen"] => print_secret(&self.generate(None)?)?,
+            [b"gen", len] => print_secret(&self.generate(Some(len))?)?,
+            [b"gen", form, prompt] => self.set(
+                form,
+                prompt,
+                self.generate(None)?,
+                Some(FieldKind::Password),
+            )?,
+            [b"gen", form, prompt, len] => self.set(
+                form,
+                prompt,
+                self.generate(Some(len))?,
+                Some(FieldKind::Password),
+            )?,
+            [b"rename", form, new_name] => self.rename(form, new_name)?,
+            [b"history", form] => self.history(form, false)?,
+            [b"history!", form] => self.history(form, true)?,
+            [b"restore", form, number] => self.restore(form, number)?,
+            [b"open", vault] => self.open_vault(vault)?,
+            [b"vaults"] => self.list_vaults(),
+            [b"switch", vault] => self.current = self.find_vault(vault)?,
+            [b"cp", form, vault] => self.copy(form, vault, form)?,
+            [b"cp", form, vault, new_name] => self.copy(form, vault, new_name)?,
+            [b"save"] => self.save(false)?,
+            [b"save!"] => self.save(true)?,
+            [b"quit" | b"q"] => return self.quit(),
+            [command, ..] if COMMANDS.iter().any(|(name, _)| name == command) => {
+                println!("Wrong number of arguments, enter help for usage")
+            }
+            _ => println!("Unrecognized command, enter help for a list of commands"),
         }
+        Ok(false)
+    }
+
+    fn current(&self) -> &Unlocked {
+        &self.vaults[self.current]
+    }
+
+    fn current_mut(&mut self) -> &mut Unlocked {
+        &mut self.vaults[self.current]
+    }
+
+    fn list(&self, filter: &Filter) {
+        for name in filter.names(&self.current().vault) {
+            println!("{}", String::from_utf8_lossy(name));
+        }
+    }
+
+    fn find(&self, query: &[u8]) -> Result<(), Error> {
+        let current = self.current();
+        for hit in search(&current.vault, current.key.expose(), query)? {
+            print_hit(hit.name, hit.matched.name());
+        }
+        Ok(())
+    }
+
+    fn info(&self, form: &[u8]) -> Result<(), Error> {
+        let meta = self
+            .current()
+            .vault
+            .meta(form)
+            .ok_or(passmogu::Error::FormNotFound)?;
+        let time =
+            |time: Option<u64>, missing| time.map_or_else(|| String::from(missing), format_time);
+        println!("created:   {}", time(meta.created, "unknown"));
+        println!("modified:  {}", time(meta.modified, "unknown"));
+        println!("last used: {}", time(meta.last_used, "never"));
+        if let Some(folder) = &meta.folder {
+            println!("folder:    {}", String::from_utf8_lossy(folder));
+        }
+        if !meta.tags.is_empty() {
+            let tags: Vec<_> = meta
+                .tags
+                .iter()
+                .map(|tag| String::from_utf8_lossy(tag))
+                .collect();
+            println!("tags:      {}", tags.join(", "));
+        }
+        if meta.favorite {
+            println!("favorite");
+        }
+        Ok(())
+    }
+
+    /// Changes a form's metadata, its fields are left alone.
+    fn edit_meta(&mut self, form: &[u8], edit: impl FnOnce(&mut FormMeta)) -> Result<(), Error> {
+        let current = self.current_mut();
+        edit(
+            current
+                .vault
+                .meta_mut(form)
+                .ok_or(passmogu::Error::FormNotFound)?,
+        );
+        current.unsaved = true;
+        Ok(())
+    }
+
+    /// Records that form was used, without counting as an unsaved change. It's saved along with
+    /// the next change.
+    fn mark_used(&mut self, form: &[u8]) {
+        if let Some(meta) = self.current_mut().vault.meta_mut(form) {
+            meta.mark_used();
+        }
+    }
+
+    /// Answers of hidden kinds, and of fields without a kind, are masked unless reveal is set.
+    fn show(&self, form: &[u8], reveal: bool) -> Result<(), Error> {
+        let mut stdout = io::stdout();
+        for field in self.current().open(form)? {
+            write!(stdout, "{}", String::from_utf8_lossy(field.prompt.expose()))?;
+            if let Some(kind) = field.kind {
+                write!(stdout, " ({kind})")?;
+            }
+            stdout.write_all(b": ")?;
+            if reveal || field.kind.is_some_and(|kind| !kind.is_hidden()) {
+                stdout.write_all(field.answer.expose())?;
+            } else {
+                stdout.write_all(b"********")?;
+            }
+            stdout.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn get(&mut self, form: &[u8], prompt: Option<&[u8]>) -> Result<(), Error> {
+        let fields = self.current().open(form)?;
+        let field = select_field(&fields, prompt)?;
+        print_secret(&field.answer)?;
+        self.mark_used(form);
+        Ok(())
+    }
+
+    fn clip(&mut self, form: &[u8], prompt: Option<&[u8]>) -> Result<(), Error> {
+        let fields = self.current().open(form)?;
+        let field = select_field(&fields, prompt)?;
+        self.mark_used(form);
+        clipboard::copy_for(field.answer.expose(), self.config.clipboard_timeout())?;
+        match self.config.clipboard_timeout {
+            0 => println!("Copied to the clipboard"),
+            secs => println!("Copied to the clipboard, clearing it in {secs} seconds"),
+        }
+        Ok(())
+    }
+
+    /// Overwrites the answer of the field with prompt, or appends a new field of kind.
+    /// The form is created if it doesn't exist.
+    fn set(
+        &mut self,
+        form: &[u8],
+        prompt: &[u8],
+        answer: Secret,
+        kind: Option<FieldKind>,
+    ) -> Result<(), Error> {
+        let mut fields = match self.current().open(form) {
+            Err(Error::Vault(passmogu::Error::FormNotFound)) => Vec::new(),
+            fields => fields?,
+        };
+        match fields
+            .iter_mut()
+            .find(|field| field.prompt.expose() == prompt)
+        {
+            Some(field) => field.answer = answer,
+            None => fields.push(Field {
+                prompt: Secret::new(Box::from(prompt)),
+                answer,
+                kind,
+            }),
+        }
+        self.current_mut().store(form, fields)
+    }
+
+    fn set_kind(&mut self, form: &[u8], prompt: &[u8], kind: &[u8]) -> Result<(), Error> {
+        let kind = match kind {
+            b"none" => None,
+            kind => Some(
+                FieldKind::from_name(kind)
+                    .ok_or_else(|| Error::UnknownFieldKind(String::from_utf8_lossy(kind).into()))?,
+            ),
+        };
+        let mut fields = self.current().open(form)?;
+        find_field(&fields, prompt)?;
+        for field in &mut fields {
+            if field.prompt.expose() == prompt {
+                field.kind = kind;
+            }
+        }
+        self.current_mut().store(form, fields)
+    }
+
+    fn remove(&mut self, form: &[u8]) -> Result<(), Error> {
+        let current = self.current_mut();
+        current
+            .vault
+            .remove(form)
+            .ok_or(passmogu::Error::FormNotFound)?;
+        current.unsaved = true;
+        Ok(())
+    }
+
+    fn remove_field(&mut self, form: &[u8], prompt: &[u8]) -> Result<(), Error> {
+        let mut fields = self.current().open(form)?;
+        find_field(&fields, prompt)?;
+        fields.retain(|field| field.prompt.expose() != prompt);
+        self.current_mut().store(form, fields)
+    }
+
+    /// Ciphertexts are bound to the form name, so the form is opened and sealed again.
+    fn rename(&mut self, form: &[u8], new_name: &[u8]) -> Result<(), Error> {
+        let current = self.current_mut();
+        current.open(form)?;
+        if current.vault.get(new_name).is_some() {
+            return Err(Error::FormExists(String::from_utf8_lossy(new_name).into()));
+        }
+        current.vault.rename(form, new_name, current.key.expose())?;
+        current.unsaved = true;
+        Ok(())
+    }
+
+    fn history(&self, form: &[u8], reveal: bool) -> Result<(), Error> {
+        let current = self.current();
+        current.open(form)?;
+        print_entries(&current.vault.history(form, current.key.expose())?, reveal)
+    }
+
+    /// Restores entry number of form's history, numbered as history lists them.
+    fn restore(&mut self, form: &[u8], number: &[u8]) -> Result<(), Error> {
+        let current = self.current_mut();
+        let history = current.vault.history(form, current.key.expose())?;
+        let number = str::from_utf8(number)
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .ok_or(passmogu::Error::HistoryNotFound)?;
+        let index = entry_index(history.len(), number)?;
+        current.vault.restore(form, current.key.expose(), index)?;
+        current.unsaved = true;
+        Ok(())
+    }
+
+    /// Unlocks another vault, by name or path, and makes it the current one.
+    fn open_vault(&mut self, vault: &[u8]) -> Result<(), Error> {
+        if let Ok(index) = self.find_vault(vault) {
+            self.current = index;
+            return Ok(());
+        }
+        let path = self.registry.resolve(&token_path(vault))?;
+        let unlocked = Unlocked::unlock(&path, self.lock_timeout, self.registry, self.config)?;
+        println!("Unlocked {}", unlocked.label);
+        self.vaults.push(unlocked);
+        self.current = self.vaults.len() - 1;
+        Ok(())
+    }
+
+    fn list_vaults(&self) {
+        for (i, vault) in self.vaults.iter().enumerate() {
+            let marker = if i == self.current { '*' } else { ' ' };
+            let unsaved = if vault.unsaved { " (unsaved)" } else { "" };
+            println!("{marker} {}{unsaved}", vault.label);
+        }
+    }
+
+    /// Index of the unlocked vault with this name or path.
+    fn find_vault(&self, vault: &[u8]) -> Result<usize, Error> {
+        let label = String::from_utf8_lossy(vault);
+        // vaults opened by path are labeled with it, so an unknown name may still be a label
+        let path = match self.registry.resolve(&token_path(vault)) {
+            Ok(path) => Some(path::absolute(path)?),
+            Err(_) => None,
+        };
+        self.vaults
+            .iter()
+            .position(|unlocked| unlocked.label == label || Some(&unlocked.path) == path.as_ref())
+            .ok_or_else(|| Error::VaultNotOpen(label.into()))
+    }
+
+    /// Copies form from the current vault into another unlocked vault as new_name.
+    /// The vaults have different keys, so the form is opened and sealed again.
+    fn copy(&mut self, form: &[u8], vault: &[u8], new_name: &[u8]) -> Result<(), Error> {
+        let target = self.find_vault(vault)?;
+        let current = self.current();
+        let sealed = current
+            .vault
+            .get(form)
+            .ok_or(passmogu::Error::FormNotFound)?;
+        let fields = open_form(form, sealed, current.key.expose())?;
+        let meta = current.vault.meta(form).cloned().unwrap_or_default();
+        let target = &mut self.vaults[target];
+        if target.vault.get(new_name).is_some() {
+            return Err(Error::FormExists(String::from_utf8_lossy(new_name).into()));
+        }
+        let sealed = seal_form(new_name, &fields, target.key.expose())?;
+        target.vault.insert(new_name, sealed);
+        // the copy is a new form, but it's filed the same way
+        if let Some(copy) = target.vault.meta_mut(new_name) {
+            copy.tags = meta.tags;
+            copy.folder = meta.folder;
+            copy.favorite = meta.favorite;
+        }
+        target.unsaved = true;
+        println!(
+            "Copied {} to {}",
+            String::from_utf8_lossy(form),
+            target.label
+        );
+        Ok(())
+    }
+
+    /// Saves every vault with unsaved changes. Others are left alone, each save would push out
+    /// a backup generation.
+    fn save(&mut self, force: bool) -> Result<(), Error> {
+        if self.vaults.iter().all(|vault| !vault.unsaved) {
+            println!("Nothing to save");
+            return Ok(());
+        }
+        for vault in self.vaults.iter_mut().filter(|vault| vault.unsaved) {
+            vault.save(force, self.lock_timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Offers to save unsaved changes, returns whether to quit.
+    fn quit(&mut self) -> Result<bool, Error> {
+        let unsaved: Vec<&str> = self
+            .vaults
+            .iter()
+            .filter(|vault| vault.unsaved)
+            .map(|vault| vault.label.as_str())
+            .collect();
+        let question = match unsaved.as_slice() {
+            [] => return Ok(true),
+            [_] if self.vaults.len() == 1 => "There are unsaved changes, save them?".into(),
+            labels => format!(
+                "There are unsaved changes in {}, save them?",
+                labels.join(", ")
+            ),
+        };
+        if input::ask(&question, true)? {
+            for vault in self.vaults.iter_mut().filter(|vault| vault.unsaved) {
+                vault.save(false, self.lock_timeout)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Forgets the keys, then asks for the master passwords until they're right.
+    /// Unsaved changes survive since the forms in memory are sealed.
+    fn relock(&mut self) -> Result<(), Error> {
+        for vault in &mut self.vaults {
+            vault.key.zeroize();
+        }
+        clipboard::clear_pending()?;
+        println!();
+        println!(
+            "Locked after {} seconds without input",
+            self.config.auto_lock_timeout
+        );
+        let labeled = self.vaults.len() > 1;
+        for vault in &mut self.vaults {
+            if labeled {
+                println!("Unlocking {}", vault.label);
+            }
+            vault.relock()?;
+        }
+        println!("Vault unlocked");
+        Ok(())
+    }
+
+    /// A password from the configured generator policy, of the configured length unless len is given.
+    fn generate(&self, len: Option<&[u8]>) -> Result<Secret, Error> {
+        let len = match len {
+            Some(len) => parse_len(len)?,
+            None => self.config.generator.length,
+        };
+        Ok(self.config.generator.policy(len).generate()?)
+    }
+}
+
+impl Unlocked {
+    /// Loads the vault file at path and asks for its master password.
+    fn unlock(
+        path: &Path,
+        lock_timeout: Duration,
+        registry: &Registry,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let (on_disk, mut vault, key) = unlock_vault(path, lock_timeout)?;
+        vault.set_history_limit(config.history_limit);
+        let label = match registry.name_of(path) {
+            Some(name) => name.into(),
+            None => path.display().to_string(),
+        };
+        Ok(Unlocked {
+            label,
+            path: path::absolute(path)?,
+            vault,
+            key,
+            on_disk,
+            unsaved: false,
+        })
+    }
+
+    /// Unless forced, fails with VaultChanged if another process wrote the vault file.
+    fn save(&mut self, force: bool, lock_timeout: Duration) -> Result<(), Error> {
+        let _lock = VaultLock::acquire(&self.path, lock_timeout)?;
+        if !force && changed_on_disk(&self.path, &self.on_disk) {
+            return Err(Error::VaultChanged(self.path.clone()));
+        }
+        let sealed = EncryptedVault::seal(&self.vault, self.key.expose())?;
+        save_vault(&self.path, &sealed)?;
+        self.on_disk = sealed;
+        self.unsaved = false;
+        println!("Saved {}", self.path.display());
+        Ok(())
+    }
+
+    /// Asks for the master password until it unlocks the vault as last loaded or saved.
+    fn relock(&mut self) -> Result<(), Error> {
+        loop {
+            let password = prompt::password(WELCOME_MSG)?;
+            match self.on_disk.unlock(password.expose()) {
+                Ok(key) => {
+                    self.key = key;
+                    return Ok(());
+                }
+                Err(passmogu::Error::Authentication) => println!("Wrong password"),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn open(&self, form: &[u8]) -> Result<Vec<Field>, Error> {
+        Ok(self.vault.get_and_open(form, self.key.expose())?.into_vec())
+    }
+
+    fn store(&mut self, form: &[u8], fields: Vec<Field>) -> Result<(), Error> {
+        let fields = fields.into_boxed_slice();
+        self.vault
+            .seal_and_insert(form, &fields, self.key.expose())?;
+        self.unsaved = true;
+        Ok(())
     }
-    Ok(())
 }
 
-fn tokenize(buffer: &[u8]) -> Vec<&[u8]> {
-    buffer.split(|byte| *byte == b' ').collect()
+pub(crate) fn find_field<'a>(fields: &'a [Field], prompt: &[u8]) -> Result<&'a Field, Error> {
+    fields
+        .iter()
+        .find(|field| field.prompt.expose() == prompt)
+        .ok_or_else(|| Error::FieldNotFound(String::from_utf8_lossy(prompt).into()))
 }
 
-/// Returns whether main loop should quit
-fn interpret(tokens: &[&[u8]]) -> Result<bool, Error> {
-    match tokens[0] {
-        b"quit" | b"q" => return Ok(true),
-        _ => println!("Unrecognized command, enter q[uit] to quit"),
+/// The field with prompt, or the form's password field if prompt is None. That's the first field
+/// of kind Password, or else the first field without a kind that looks like a password.
+pub(crate) fn select_field<'a>(
+    fields: &'a [Field],
+    prompt: Option<&[u8]>,
+) -> Result<&'a Field, Error> {
+    if let Some(prompt) = prompt {
+        return find_field(fields, prompt);
     }
-    Ok(false)
+    let password = Some(FieldKind::Password);
+    fields
+        .iter()
+        .find(|field| field.kind == password)
+        .or_else(|| {
+            fields.iter().find(|field| {
+                field.kind.is_none()
+                    && FieldKind::guess(field.prompt.expose(), field.answer.expose()) == password
+            })
+        })
+        .ok_or(Error::NoPasswordField)
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time, e.g. "2024-02-29 13:05:00 UTC".
+pub(crate) fn format_time(time: u64) -> String {
+    let (days, seconds) = (time / 86400, time % 86400);
+    // days to a civil date, from Howard Hinnant's chrono-compatible date algorithms
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // March is 0
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// A vault name or path typed in the session.
+fn token_path(token: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(token).into_owned())
+}
+
+fn parse_len(token: &[u8]) -> Result<usize, Error> {
+    str::from_utf8(token)
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .filter(|len| *len <= MAX_LENGTH)
+        .ok_or(Error::InvalidLength)
+}
+
+/// items sorted without duplicates.
+fn unique<'a>(items: impl Iterator<Item = &'a [u8]>) -> Vec<&'a [u8]> {
+    let mut items: Vec<_> = items.collect();
+    items.sort_unstable();
+    items.dedup();
+    items
+}
+
+/// Writes secret to stdout followed by a newline, without copying it into a String.
+pub(crate) fn print_secret(secret: &Secret) -> Result<(), Error> {
+    let mut stdout = io::stdout();
+    stdout.write_all(secret.expose())?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Asks for a new master password twice, failing if they differ.
+pub(crate) fn prompt_new_password() -> Result<Secret, Error> {
+    Ok(prompt::confirmed_password(
+        "New master password: ",
+        "Repeat new master password: ",
+    )?)
 }
 
-/// Returns master key.
-fn unlock_vault() -> Result<Secret, Error> {
-    print!("{WELCOME_MSG}");
-    io::stdout().flush()?;
-    let mut master_password = Secret::zero(MAX_INPUT_LINE_LEN);
-    read_line(master_password.expose_mut())?;
-    Ok(derive_key(
-        master_password.expose(),
-        b"TODO: dummy salt string",
-    ))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::read_vault;
+    use crate::test_util::{TempDir, test_header};
+
+    /// Session on an empty vault, saved at path.
+    fn test_session<'a>(path: &Path, config: &'a Config, registry: &'a Registry) -> Session<'a> {
+        let (header, key) = test_header();
+        let vault = Vault::new(header);
+        let on_disk = EncryptedVault::seal(&vault, key.expose()).unwrap();
+        save_vault(path, &on_disk).unwrap();
+        let first = Unlocked {
+            label: "test".into(),
+            path: path.into(),
+            vault,
+            key,
+            on_disk,
+            unsaved: false,
+        };
+        Session {
+            lock_timeout: Duration::ZERO,
+            config,
+            registry,
+            vaults: vec![first],
+            current: 0,
+        }
+    }
+
+    /// Answers of form's fields, in order.
+    fn answers(session: &Session, form: &[u8]) -> Vec<Vec<u8>> {
+        let fields = session.current().open(form).unwrap();
+        fields
+            .iter()
+            .map(|field| field.answer.expose().into())
+            .collect()
+    }
+
+    /// The vault file of the current vault, opened.
+    fn saved_vault(session: &Session) -> Vault {
+        let current = session.current();
+        let sealed = read_vault(&current.path).unwrap();
+        sealed.open(current.key.expose()).unwrap()
+    }
+
+    #[test]
+    fn interpret_commands() {
+        let dir = TempDir::new("session");
+        let path = dir.0.join("vault");
+        let (config, registry) = (Config::default(), Registry::default());
+        let mut session = test_session(&path, &config, &registry);
+
+        assert!(
+            !session
+                .interpret(&[b"set", b"irc", b"password", b"hunter2"])
+                .unwrap()
+        );
+        assert!(session.current().unsaved);
+        assert_eq!(answers(&session, b"irc"), [b"hunter2"]);
+        session
+            .interpret(&[b"set", b"irc", b"password", b"hunter3"])
+            .unwrap();
+        assert_eq!(answers(&session, b"irc"), [b"hunter3"]);
+
+        session.interpret(&[b"save"]).unwrap();
+        assert!(!session.current().unsaved);
+        let saved = saved_vault(&session);
+        assert_eq!(saved, session.current().vault);
+        assert!(saved.get(b"irc").is_some());
+
+        // using a form isn't a change worth saving on its own
+        session.interpret(&[b"get", b"irc"]).unwrap();
+        assert!(!session.current().unsaved);
+        assert!(
+            session
+                .current()
+                .vault
+                .meta(b"irc")
+                .unwrap()
+                .last_used
+                .is_some()
+        );
+        assert!(session.interpret(&[b"get", b"nope"]).is_err());
+
+        session
+            .interpret(&[b"set", b"bank", b"pin", b"0000"])
+            .unwrap();
+        session.interpret(&[b"save"]).unwrap();
+        assert!(matches!(
+            session.interpret(&[b"rename", b"irc", b"bank"]),
+            Err(Error::FormExists(_))
+        ));
+        assert!(!session.current().unsaved);
+        session.interpret(&[b"rename", b"irc", b"chat"]).unwrap();
+        assert!(session.current().unsaved);
+        assert!(session.current().vault.get(b"irc").is_none());
+        assert_eq!(answers(&session, b"chat"), [b"hunter3"]);
+
+        session.interpret(&[b"save"]).unwrap();
+        session
+            .interpret(&[b"gen", b"chat", b"2fa backup", b"12"])
+            .unwrap();
+        assert!(session.current().unsaved);
+        let fields = session.current().open(b"chat").unwrap();
+        assert_eq!(fields[1].prompt.expose(), b"2fa backup");
+        assert_eq!(fields[1].answer.len(), 12);
+        assert_eq!(fields[1].kind, Some(FieldKind::Password));
+
+        assert!(matches!(
+            session.interpret(&[b"gen", b"chat", b"pin", b"4097"]),
+            Err(Error::InvalidLength)
+        ));
+
+        session.interpret(&[b"save"]).unwrap();
+        session.interpret(&[b"rm", b"chat", b"2fa backup"]).unwrap();
+        assert!(session.current().unsaved);
+        assert_eq!(answers(&session, b"chat"), [b"hunter3"]);
+        session.interpret(&[b"rm", b"chat"]).unwrap();
+        assert!(session.current().vault.get(b"chat").is_none());
+        assert!(session.interpret(&[b"rm", b"chat"]).is_err());
+
+        session.interpret(&[b"save"]).unwrap();
+        assert!(!session.current().unsaved);
+        assert_eq!(
+            saved_vault(&session).form_names().collect::<Vec<_>>(),
+            [&b"bank"[..]]
+        );
+
+        // mistakes leave the session running
+        assert!(!session.interpret(&[b"rm"]).unwrap());
+        assert!(!session.interpret(&[b"nope"]).unwrap());
+        assert!(session.interpret(&[b"quit"]).unwrap());
+    }
+
+    #[test]
+    fn format_times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_time(1_709_211_900), "2024-02-29 13:05:00 UTC");
+        assert_eq!(format_time(4_102_444_799), "2099-12-31 23:59:59 UTC");
+    }
 }
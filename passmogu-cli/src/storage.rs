@@ -0,0 +1,320 @@
+use crate::error::Error;
+use crate::input;
+use crate::lock::VaultLock;
+use crate::session::WELCOME_MSG;
+use passmogu::prompt;
+use passmogu::secret::Secret;
+use passmogu::vault::{EncryptedVault, Vault};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Backup generations kept next to the vault file, from vault.bak.1 (newest) to vault.bak.BACKUPS.
+pub(crate) const BACKUPS: usize = 3;
+
+/// Reads and parses the vault file at path, for callers that already hold the vault's lock.
+/// A corrupt file may be restored from a backup, see load.
+pub(crate) fn load_locked_vault(path: &Path, _lock: &VaultLock) -> Result<EncryptedVault, Error> {
+    load(path, &mut || Ok(None))
+}
+
+/// Loads the vault file at path, asks for its master password and opens it, see unlock.
+/// Returns the vault file as loaded, the opened vault and its data key.
+pub(crate) fn unlock_vault(
+    path: &Path,
+    lock_timeout: Duration,
+) -> Result<(EncryptedVault, Vault, Secret), Error> {
+    unlock(path, || VaultLock::acquire(path, lock_timeout).map(Some))
+}
+
+/// Like unlock_vault, for callers that already hold the vault's lock.
+pub(crate) fn unlock_locked_vault(
+    path: &Path,
+    _lock: &VaultLock,
+) -> Result<(EncryptedVault, Vault, Secret), Error> {
+    unlock(path, || Ok(None))
+}
+
+/// Reads and parses the vault file at path. If it's corrupt, offers to replace it with the newest
+/// backup that still parses, with lock locking the vault for that unless it's locked already.
+fn load(
+    path: &Path,
+    lock: &mut impl FnMut() -> Result<Option<VaultLock>, Error>,
+) -> Result<EncryptedVault, Error> {
+    match read_vault(path) {
+        Err(Error::Vault(error)) => recover(path, error.into(), None, lock),
+        result => result,
+    }
+}
+
+/// load, then unlocks the header with the master password and opens the blob with the data key.
+/// Once the header has taken the password, a blob that doesn't decrypt can only be corrupt, so
+/// the newest backup that opens with the same key is offered in its place.
+fn unlock(
+    path: &Path,
+    mut lock: impl FnMut() -> Result<Option<VaultLock>, Error>,
+) -> Result<(EncryptedVault, Vault, Secret), Error> {
+    let sealed = load(path, &mut lock)?;
+    let master_password = prompt::password(WELCOME_MSG)?;
+    let key = sealed.unlock(master_password.expose())?;
+    let sealed = match sealed.open(key.expose()) {
+        Ok(vault) => return Ok((sealed, vault, key)),
+        Err(passmogu::Error::Authentication) => {
+            recover(path, Error::CorruptBlob, Some(&key), &mut lock)?
+        }
+        Err(error) => return Err(error.into()),
+    };
+    let vault = sealed.open(key.expose())?;
+    Ok((sealed, vault, key))
+}
+
+/// Offers to replace the corrupt vault file at path with its newest valid backup, see
+/// newest_valid_backup. Returns error if there's none or the offer is declined.
+fn recover(
+    path: &Path,
+    error: Error,
+    key: Option<&Secret>,
+    lock: &mut impl FnMut() -> Result<Option<VaultLock>, Error>,
+) -> Result<EncryptedVault, Error> {
+    let Some((backup, vault)) = newest_valid_backup(path, key) else {
+        return Err(error);
+    };
+    eprintln!("{} is corrupt: {error}", path.display());
+    let question = format!("Restore the newest valid backup {}?", backup.display());
+    if !input::ask(&question, false)? {
+        return Err(error);
+    }
+    let _lock = lock()?;
+    // another process may have written the vault while we were asking
+    if let Ok(vault) = read_vault(path)
+        && opens(&vault, key)
+    {
+        return Ok(vault);
+    }
+    // the corrupt file isn't worth a backup generation
+    write_atomically(path, &vault.dump())?;
+    eprintln!("Restored {} from {}", path.display(), backup.display());
+    Ok(vault)
+}
+
+/// Replaces the vault file at path all at once, see write_atomically.
+/// The file being replaced becomes the newest backup, the oldest backup is dropped.
+pub(crate) fn save_vault(path: &Path, vault: &EncryptedVault) -> Result<(), Error> {
+    rotate_backups(path)?;
+    write_atomically(path, &vault.dump())
+}
+
+/// Replaces the vault file at path with backup generation (1 is the newest), or with the newest
+/// backup that parses if generation is None. Returns the backup's path.
+/// A vault file that still parses is kept as the newest backup, so a restore can be undone.
+pub(crate) fn restore_backup(path: &Path, generation: Option<usize>) -> Result<PathBuf, Error> {
+    let (backup, vault) = match generation {
+        Some(generation) => {
+            let backup = backup_path(path, generation);
+            let vault = read_vault(&backup)?;
+            (backup, vault)
+        }
+        None => newest_valid_backup(path, None).ok_or(Error::NoBackup)?,
+    };
+    if read_vault(path).is_ok() {
+        save_vault(path, &vault)?;
+    } else {
+        write_atomically(path, &vault.dump())?;
+    }
+    Ok(backup)
+}
+
+/// Returns whether the vault file at path no longer holds vault, e.g. because another process
+/// saved over it. A file that can't be read or parsed counts as changed.
+pub(crate) fn changed_on_disk(path: &Path, vault: &EncryptedVault) -> bool {
+    read_vault(path).ok().as_ref() != Some(vault)
+}
+
+/// Reads and parses the vault file at path, without offering any backups.
+pub(crate) fn read_vault(path: &Path) -> Result<EncryptedVault, Error> {
+    Ok(EncryptedVault::load(&fs::read(path)?)?)
+}
+
+/// The vault is written to a temporary file next to path, synced and renamed over the original,
+/// so a crash midway leaves either the old or the new vault.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let temp_path = sibling(path, ".tmp");
+    let mut file = create_private(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp_path, path)?;
+    sync_parent(path)
+}
+
+/// Shifts vault.bak.1 to vault.bak.2 and so on, then makes the current vault file vault.bak.1.
+fn rotate_backups(path: &Path) -> Result<(), Error> {
+    if !path.exists() {
+        return Ok(());
+    }
+    for generation in (1..BACKUPS).rev() {
+        let older = backup_path(path, generation);
+        if older.exists() {
+            fs::rename(&older, backup_path(path, generation + 1))?;
+        }
+    }
+    // a copy rather than a hard link, so the backup survives whatever might corrupt the vault file
+    let newest = backup_path(path, 1);
+    fs::copy(path, &newest)?;
+    File::open(&newest)?.sync_all()?;
+    sync_parent(path)
+}
+
+/// Returns the newest backup of the vault at path that can be read and parsed, and that opens
+/// with key if it's given.
+fn newest_valid_backup(path: &Path, key: Option<&Secret>) -> Option<(PathBuf, EncryptedVault)> {
+    (1..=BACKUPS).find_map(|generation| {
+        let backup = backup_path(path, generation);
+        let vault = read_vault(&backup).ok()?;
+        opens(&vault, key).then_some((backup, vault))
+    })
+}
+
+/// Returns whether vault opens with key, or true without a key to check.
+fn opens(vault: &EncryptedVault, key: Option<&Secret>) -> bool {
+    key.is_none_or(|key| vault.open(key.expose()).is_ok())
+}
+
+fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    sibling(path, &format!(".bak.{generation}"))
+}
+
+/// Creates (or truncates) a file only the current user can read and write.
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Renames and new files are only durable once their directory is synced.
+fn sync_parent(path: &Path) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Returns path with suffix appended to the file name, e.g. "vault" -> "vault.tmp".
+pub(crate) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, field, test_header};
+    use passmogu::header::Header;
+
+    /// Sealed vault holding a single form with the given name, under header and its data key.
+    fn vault_with(name: &[u8], (header, key): &(Header, Secret)) -> EncryptedVault {
+        let mut vault = Vault::new(header.clone());
+        vault
+            .seal_and_insert(name, &[field(b"password", b"hunter2")].into(), key.expose())
+            .unwrap();
+        EncryptedVault::seal(&vault, key.expose()).unwrap()
+    }
+
+    #[test]
+    fn rotate_backups_on_save() {
+        let dir = TempDir::new("rotate");
+        let path = dir.0.join("vault");
+        let keys = test_header();
+        let saves: Vec<_> = (0..BACKUPS + 2)
+            .map(|i| vault_with(format!("form {i}").as_bytes(), &keys))
+            .collect();
+        for vault in &saves {
+            save_vault(&path, vault).unwrap();
+        }
+        assert_eq!(read_vault(&path).unwrap(), saves[BACKUPS + 1]);
+        for generation in 1..=BACKUPS {
+            let backup = read_vault(&backup_path(&path, generation)).unwrap();
+            assert_eq!(backup, saves[BACKUPS + 1 - generation]);
+        }
+        assert!(!backup_path(&path, BACKUPS + 1).exists());
+        assert!(!sibling(&path, ".tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn restore_newest_valid_backup() {
+        let dir = TempDir::new("restore");
+        let path = dir.0.join("vault");
+        assert!(matches!(restore_backup(&path, None), Err(Error::NoBackup)));
+        let keys = test_header();
+        let old = vault_with(b"old", &keys);
+        let new = vault_with(b"new", &keys);
+        save_vault(&path, &old).unwrap();
+        save_vault(&path, &new).unwrap();
+        save_vault(&path, &new).unwrap();
+        // truncated by something that didn't write atomically, by whole bytes of the blob
+        let dumped = new.dump();
+        fs::write(&path, [&dumped[..dumped.len() - 3], b"\n"].concat()).unwrap();
+        fs::write(backup_path(&path, 1), b"garbage").unwrap();
+
+        let backup = restore_backup(&path, None).unwrap();
+        assert_eq!(backup, backup_path(&path, 2));
+        assert_eq!(read_vault(&path).unwrap(), old);
+        // nothing was rotated for the corrupt file
+        assert_eq!(read_vault(&backup_path(&path, 2)).unwrap(), old);
+
+        // restoring over a valid vault keeps it as a backup
+        save_vault(&path, &new).unwrap();
+        let backup = restore_backup(&path, Some(1)).unwrap();
+        assert_eq!(backup, backup_path(&path, 1));
+        assert_eq!(read_vault(&path).unwrap(), old);
+        assert_eq!(read_vault(&backup_path(&path, 1)).unwrap(), new);
+        assert!(matches!(
+            restore_backup(&path, Some(BACKUPS + 1)),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn skip_backups_that_dont_open() {
+        let dir = TempDir::new("flipped");
+        let path = dir.0.join("vault");
+        let keys = test_header();
+        let old = vault_with(b"old", &keys);
+        let new = vault_with(b"new", &keys);
+        save_vault(&path, &old).unwrap();
+        save_vault(&path, &new).unwrap();
+        save_vault(&path, &new).unwrap();
+        // a flipped byte in the blob's tag still parses, but no longer opens
+        let mut flipped = new.dump().to_vec();
+        let digit = flipped.len() - 2;
+        flipped[digit] = if flipped[digit] == b'0' { b'1' } else { b'0' };
+        fs::write(backup_path(&path, 1), &flipped).unwrap();
+        assert!(read_vault(&backup_path(&path, 1)).is_ok());
+
+        let (backup, vault) = newest_valid_backup(&path, Some(&keys.1)).unwrap();
+        assert_eq!(backup, backup_path(&path, 2));
+        assert_eq!(vault, old);
+        // without the key, only its size can be checked
+        let (backup, _) = newest_valid_backup(&path, None).unwrap();
+        assert_eq!(backup, backup_path(&path, 1));
+        // nor does a backup of some other vault open
+        let other = test_header();
+        assert!(newest_valid_backup(&path, Some(&other.1)).is_none());
+    }
+}
@@ -0,0 +1,254 @@
+#[cfg(unix)]
+use crate::agent;
+use crate::config::{Config, Generator};
+use crate::error::Error;
+use crate::lock::VaultLock;
+#[cfg(unix)]
+use crate::query::Filter;
+use crate::storage::{save_vault, unlock_locked_vault};
+use passmogu::{
+    prompt,
+    secret::Secret,
+    vault::{EncryptedVault, Field, FieldKind, Form, Vault, open_form},
+};
+use std::path::Path;
+use std::time::Duration;
+
+/// What to do when the form being stored already exists.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Existing {
+    /// refuse to touch it
+    Keep,
+    /// throw away all of its fields
+    Replace,
+    /// overwrite fields with the same prompt, append the others
+    Merge,
+}
+
+/// Stores a form built from "prompt=answer" field arguments into the vault at path.
+/// An answer of "@generate:N" is replaced by a password of length N from generator, "@generate"
+/// by one of the configured length, "@-" reads the answer from stdin and a leading "@@" stands for a literal "@".
+/// Generated answers are passwords, other fields get the kind FieldKind::guess makes of them.
+pub(crate) fn store_form(
+    path: &Path,
+    name: &str,
+    fields: &[String],
+    existing: Existing,
+    lock_timeout: Duration,
+    config: &Config,
+) -> Result<(), Error> {
+    let name = name.as_bytes();
+    let generator = &config.generator;
+    let exists = || Error::FormExists(String::from_utf8_lossy(name).into());
+    #[cfg(unix)]
+    if let Some(mut agent) = agent::Client::connect(path)? {
+        // check before asking for any answers, the agent checks again when storing
+        if existing == Existing::Keep
+            && agent
+                .list(&Filter::default())?
+                .iter()
+                .any(|form| form.expose() == name)
+        {
+            return Err(exists());
+        }
+        let form = parse_fields(fields, generator)?;
+        let overwritten = agent.store(name, form, existing)?;
+        report(name, overwritten.as_deref());
+        return Ok(());
+    }
+
+    let lock = VaultLock::acquire(path, lock_timeout)?;
+    let (_, mut vault, key) = unlock_locked_vault(path, &lock)?;
+    vault.set_history_limit(config.history_limit);
+    // check before asking for any answers
+    if existing == Existing::Keep && vault.get(name).is_some() {
+        return Err(exists());
+    }
+    let form = parse_fields(fields, generator)?;
+    let overwritten = insert_form(&mut vault, key.expose(), name, form, existing)?;
+    save_vault(path, &EncryptedVault::seal(&vault, key.expose())?)?;
+    report(name, overwritten.map(|old| prompts(&old)).as_deref());
+    Ok(())
+}
+
+/// Seals form into vault under name, unless existing says to keep a form that's already there.
+/// Returns the form that was replaced, opened.
+pub(crate) fn insert_form(
+    vault: &mut Vault,
+    key: &[u8],
+    name: &[u8],
+    mut form: Vec<Field>,
+    existing: Existing,
+) -> Result<Option<Form>, Error> {
+    if vault.get(name).is_some() {
+        match existing {
+            Existing::Keep => {
+                return Err(Error::FormExists(String::from_utf8_lossy(name).into()));
+            }
+            Existing::Replace => (),
+            Existing::Merge => form = merge_fields(vault.get_and_open(name, key)?, form),
+        }
+    }
+    let overwritten = vault.seal_and_insert(name, &form.into_boxed_slice(), key)?;
+    Ok(overwritten
+        .map(|old| open_form(name, &old, key))
+        .transpose()?)
+}
+
+/// previous is the prompts of the form that was overwritten, if any.
+fn report(name: &[u8], previous: Option<&str>) {
+    match previous {
+        Some(prompts) => println!(
+            "Overwrote form {}, previous fields: {prompts}",
+            String::from_utf8_lossy(name),
+        ),
+        None => println!("Stored form {}", String::from_utf8_lossy(name)),
+    }
+}
+
+fn parse_fields(fields: &[String], generator: &Generator) -> Result<Vec<Field>, Error> {
+    fields
+        .iter()
+        .map(|field| parse_field(field, generator))
+        .collect()
+}
+
+/// Parses one --field argument, see store_form.
+fn parse_field(argument: &str, generator: &Generator) -> Result<Field, Error> {
+    let invalid = |prompt: &str| Error::InvalidField(prompt.into());
+    let (prompt, answer) = argument.split_once('=').ok_or_else(|| invalid(argument))?;
+    if prompt.is_empty() {
+        return Err(invalid(prompt));
+    }
+    // anything else starting with "@generate" is rejected below
+    let generated = answer.starts_with("@generate");
+    let answer = if answer == "@generate" {
+        generator.policy(generator.length).generate()?
+    } else if let Some(len) = answer.strip_prefix("@generate:") {
+        let len = len.parse().map_err(|_| invalid(prompt))?;
+        generator.policy(len).generate()?
+    } else if answer == "@-" {
+        prompt::password(&format!("{prompt}: "))?
+    } else if let Some(literal) = answer.strip_prefix("@@") {
+        Secret::new([b"@", literal.as_bytes()].concat().into())
+    } else if answer.starts_with('@') {
+        return Err(invalid(prompt));
+    } else {
+        Secret::new(answer.as_bytes().into())
+    };
+    let kind = match generated {
+        true => Some(FieldKind::Password),
+        false => FieldKind::guess(prompt.as_bytes(), answer.expose()),
+    };
+    Ok(Field {
+        prompt: Secret::new(prompt.as_bytes().into()),
+        answer,
+        kind,
+    })
+}
+
+/// Overwrites the answers of old fields whose prompt appears in new, appends the rest of new.
+/// Old fields keep their kind if they have one.
+fn merge_fields(old: Form, new: Vec<Field>) -> Vec<Field> {
+    let mut merged = old.into_vec();
+    for field in new {
+        match merged.iter_mut().find(|old| old.prompt == field.prompt) {
+            Some(old) => {
+                old.answer = field.answer;
+                old.kind = old.kind.or(field.kind);
+            }
+            None => merged.push(field),
+        }
+    }
+    merged
+}
+
+/// Comma separated prompts of form, answers are left out.
+pub(crate) fn prompts(form: &Form) -> String {
+    let prompts: Vec<_> = form
+        .iter()
+        .map(|field| String::from_utf8_lossy(field.prompt.expose()))
+        .collect();
+    prompts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::field;
+
+    #[test]
+    fn parse_field_arguments() {
+        let generator = Generator::default();
+        let parse = |argument: &str| parse_field(argument, &generator);
+        let invalid = |argument| matches!(parse(argument), Err(Error::InvalidField(_)));
+
+        let username = parse("username=AzureDiamond").unwrap();
+        assert_eq!(username.prompt.expose(), b"username");
+        assert_eq!(username.answer.expose(), b"AzureDiamond");
+        assert_eq!(username.kind, Some(FieldKind::Login));
+        // only the first '=' splits
+        let equation = parse("note=a=b").unwrap();
+        assert_eq!(equation.answer.expose(), b"a=b");
+        assert_eq!(parse("empty=").unwrap().answer.expose(), b"");
+
+        let generated = parse("pin=@generate").unwrap();
+        assert_eq!(generated.answer.len(), generator.length);
+        assert_eq!(generated.kind, Some(FieldKind::Password));
+        let generated = parse("pin=@generate:12").unwrap();
+        assert_eq!(generated.answer.len(), 12);
+        assert_eq!(generated.kind, Some(FieldKind::Password));
+        assert!(invalid("pin=@generate:x"));
+        assert!(matches!(
+            parse("pin=@generate:4097"),
+            Err(Error::Vault(passmogu::Error::InvalidLength))
+        ));
+        assert!(invalid("pin=@generated"));
+
+        assert_eq!(parse("at=@@home").unwrap().answer.expose(), b"@home");
+        assert_eq!(parse("at=@@").unwrap().answer.expose(), b"@");
+        assert!(invalid("at=@home"));
+        assert!(invalid("username"));
+        assert!(invalid("=AzureDiamond"));
+    }
+
+    #[test]
+    fn merge_into_existing_fields() {
+        let old: Form = [
+            Field {
+                kind: Some(FieldKind::Login),
+                ..field(b"name", b"AzureDiamond")
+            },
+            field(b"password", b"hunter2"),
+        ]
+        .into();
+        let new = vec![
+            Field {
+                kind: Some(FieldKind::Note),
+                ..field(b"name", b"Cthon98")
+            },
+            Field {
+                kind: Some(FieldKind::Password),
+                ..field(b"password", b"hunter3")
+            },
+            field(b"pin", b"0000"),
+        ];
+        let merged = merge_fields(old, new);
+        assert_eq!(
+            merged,
+            [
+                // the existing kind is kept, and fills in a missing one
+                Field {
+                    kind: Some(FieldKind::Login),
+                    ..field(b"name", b"Cthon98")
+                },
+                Field {
+                    kind: Some(FieldKind::Password),
+                    ..field(b"password", b"hunter3")
+                },
+                // new prompts are appended
+                field(b"pin", b"0000"),
+            ]
+        );
+    }
+}
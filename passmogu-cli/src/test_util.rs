@@ -0,0 +1,42 @@
+use passmogu::encrypt::KdfParams;
+use passmogu::header::Header;
+use passmogu::secret::Secret;
+use passmogu::vault::Field;
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+
+/// Header for the master password hunter2 and its data key, quick to unlock.
+pub fn test_header() -> (Header, Secret) {
+    let kdf = KdfParams::Pbkdf2HmacSha256 {
+        iterations: NonZeroU32::new(1).unwrap(),
+    };
+    Header::new(kdf, b"hunter2").unwrap()
+}
+
+/// Field of no particular kind.
+pub fn field(prompt: &[u8], answer: &[u8]) -> Field {
+    Field {
+        prompt: Secret::new(prompt.into()),
+        answer: Secret::new(answer.into()),
+        kind: None,
+    }
+}
+
+/// A fresh directory for one test, removed when dropped.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("passmogu-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,20 +1,124 @@
+use crate::error::Error;
 use crate::secret::Secret;
-use aws_lc_rs::{aead, pbkdf2};
+use aws_lc_rs::{aead, pbkdf2, rand};
+use std::fmt;
 use std::num::NonZeroU32;
 use zeroize::Zeroizing;
 
 /// We can afford the performance penalty of SIV, still don't reuse nonces.
 const ALGORITHM: &aead::Algorithm = &aead::AES_256_GCM_SIV;
 
+/// Default PBKDF2 work factor.
+pub const PBKDF2_ITERATIONS: NonZeroU32 = NonZeroU32::new(300_000_u32).unwrap();
+
+/// Selects a key derivation function along with its tuning parameters.
+/// New vaults should prefer one of the memory-hard functions, PBKDF2 remains for older vaults.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KdfParams {
+    Pbkdf2HmacSha256 {
+        iterations: NonZeroU32,
+    },
+    Argon2id {
+        /// memory cost in KiB
+        memory_kib: u32,
+        /// number of passes over memory
+        iterations: u32,
+        /// degree of parallelism
+        parallelism: u32,
+    },
+    Scrypt {
+        /// log2 of the CPU/memory cost N
+        log_n: u8,
+        /// block size
+        r: u32,
+        /// parallelization
+        p: u32,
+    },
+}
+
+impl Default for KdfParams {
+    /// Argon2id with the minimum parameters recommended by OWASP.
+    fn default() -> Self {
+        KdfParams::Argon2id {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            KdfParams::Pbkdf2HmacSha256 { iterations } => {
+                write!(f, "PBKDF2-HMAC-SHA256, {iterations} iterations")
+            }
+            KdfParams::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => write!(
+                f,
+                "Argon2id, {memory_kib} KiB memory, {iterations} iterations, parallelism {parallelism}"
+            ),
+            KdfParams::Scrypt { log_n, r, p } => write!(f, "scrypt, N=2^{log_n}, r={r}, p={p}"),
+        }
+    }
+}
+
+impl KdfParams {
+    /// Returns the secret symmetric encryption key derived from password with these parameters.
+    /// Fails when the parameters (or salt length) are out of range for the chosen function.
+    pub fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<Secret, Error> {
+        match *self {
+            KdfParams::Pbkdf2HmacSha256 { iterations } => Ok(pbkdf2(password, salt, iterations)),
+            KdfParams::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = argon2::Params::new(
+                    memory_kib,
+                    iterations,
+                    parallelism,
+                    Some(ALGORITHM.key_len()),
+                )
+                .map_err(|_| Error::InvalidKdfParams)?;
+                let argon2 = argon2::Argon2::new(
+                    argon2::Algorithm::Argon2id,
+                    argon2::Version::V0x13,
+                    params,
+                );
+                let mut result = Secret::zero(ALGORITHM.key_len());
+                argon2
+                    .hash_password_into(password, salt, result.expose_mut())
+                    .map_err(|_| Error::InvalidKdfParams)?;
+                Ok(result)
+            }
+            KdfParams::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(log_n, r, p, ALGORITHM.key_len())
+                    .map_err(|_| Error::InvalidKdfParams)?;
+                let mut result = Secret::zero(ALGORITHM.key_len());
+                scrypt::scrypt(password, salt, &params, result.expose_mut())
+                    .map_err(|_| Error::InvalidKdfParams)?;
+                Ok(result)
+            }
+        }
+    }
+}
+
 /// Returns the secret symmetric encryption key derived from password.
 /// The key will be a u8 slice length ALGORITHM.key_len().
 pub fn derive_key(password: &[u8], salt: &[u8]) -> Secret {
-    const PBKDF2_ITERATIONS: NonZeroU32 = NonZeroU32::new(300_000_u32).unwrap();
+    pbkdf2(password, salt, PBKDF2_ITERATIONS)
+}
 
+/// Same as derive_key, but with an explicit PBKDF2-HMAC-SHA256 iteration count.
+pub fn pbkdf2(password: &[u8], salt: &[u8], iterations: NonZeroU32) -> Secret {
     let mut result = Secret::zero(ALGORITHM.key_len());
     pbkdf2::derive(
         pbkdf2::PBKDF2_HMAC_SHA256,
-        PBKDF2_ITERATIONS,
+        iterations,
         salt,
         password,
         result.expose_mut(),
@@ -22,14 +126,57 @@ pub fn derive_key(password: &[u8], salt: &[u8]) -> Secret {
     result
 }
 
-/// Encrypts plaintext into (nonce, ciphertext, tag) concatenated in a Secret.
-/// Even though what's returned is ciphertext, it doesn't cost us much to zero it out anyway.
-pub fn encrypt(mut plaintext: Secret, key: &[u8]) -> Option<Secret> {
-    let aead_key = aead::RandomizedNonceKey::new(ALGORITHM, key).ok()?;
+/// Returns a new random symmetric encryption key of length ALGORITHM.key_len().
+/// Can only fail if the system RNG fails.
+pub fn generate_key() -> Result<Secret, Error> {
+    let mut key = Secret::zero(ALGORITHM.key_len());
+    rand::fill(key.expose_mut()).map_err(|_| Error::Rng)?;
+    Ok(key)
+}
+
+/// Ciphertext is the output of encrypt: (nonce, ciphertext, tag) concatenated.
+/// It's a distinct type from Secret so that plaintext can't be handed to decrypt by mistake.
+/// Even though it's ciphertext, it doesn't cost us much to zero it out anyway.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Ciphertext(Secret);
+
+impl Ciphertext {
+    /// Wraps bytes previously produced by encrypt, e.g. after loading them from storage.
+    /// Fails if bytes are too short to hold a nonce and a tag.
+    pub fn new(bytes: Secret) -> Option<Self> {
+        if bytes.len() < aead::NONCE_LEN + ALGORITHM.tag_len() {
+            return None;
+        }
+        Some(Ciphertext(bytes))
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        self.0.expose()
+    }
+
+    /// Length of the plaintext this decrypts to.
+    pub(crate) fn plaintext_len(&self) -> usize {
+        self.0.len() - aead::NONCE_LEN - ALGORITHM.tag_len()
+    }
+}
+
+/// Encrypts plaintext into a Ciphertext.
+pub fn encrypt(plaintext: Secret, key: &[u8]) -> Result<Ciphertext, Error> {
+    encrypt_with_aad(plaintext, key, &[])
+}
+
+/// Like encrypt, but authenticates associated data (aad) along with the ciphertext.
+/// The ciphertext only decrypts when given the same aad, e.g. describing where it's stored.
+pub fn encrypt_with_aad(
+    mut plaintext: Secret,
+    key: &[u8],
+    aad: &[u8],
+) -> Result<Ciphertext, Error> {
+    let aead_key = aead::RandomizedNonceKey::new(ALGORITHM, key).map_err(|_| Error::Crypto)?;
 
     let (nonce, tag) = aead_key
-        .seal_in_place_separate_tag(aead::Aad::empty(), plaintext.expose_mut())
-        .ok()?;
+        .seal_in_place_separate_tag(aead::Aad::from(aad), plaintext.expose_mut())
+        .map_err(|_| Error::Crypto)?;
     // at this point "plaintext" contains the ciphertext (eww aws_lc_rs uses out parameters)
     let ciphertext = &plaintext;
 
@@ -47,25 +194,29 @@ pub fn encrypt(mut plaintext: Secret, key: &[u8]) -> Option<Secret> {
     for i in 0..ALGORITHM.tag_len() {
         result[i + aead::NONCE_LEN + ciphertext.len()] = tag.as_ref()[i];
     }
-    Some(result)
+    Ok(Ciphertext(result))
 }
 
 /// Decrypts ciphertext into plaintext.
-/// If you decrypt plaintext, there's a good chance of panic at runtime.
-/// TODO: determine whether we can use type state pattern here to prevent that.
-pub fn decrypt(mut ciphertext: Secret, key: &[u8]) -> Option<Secret> {
-    assert!(!ciphertext.is_empty() && ciphertext.len() >= aead::NONCE_LEN); // fail fast
-    let aead_key = aead::RandomizedNonceKey::new(&aead::AES_256_GCM_SIV, key).ok()?;
+/// Fails if the key is wrong or the ciphertext has been tampered with.
+pub fn decrypt(ciphertext: Ciphertext, key: &[u8]) -> Result<Secret, Error> {
+    decrypt_with_aad(ciphertext, key, &[])
+}
+
+/// Like decrypt, fails unless aad matches the associated data the ciphertext was encrypted with.
+pub fn decrypt_with_aad(ciphertext: Ciphertext, key: &[u8], aad: &[u8]) -> Result<Secret, Error> {
+    let aead_key = aead::RandomizedNonceKey::new(ALGORITHM, key).map_err(|_| Error::Crypto)?;
 
+    // Ciphertext::new guarantees there's room for the nonce
+    let mut ciphertext = ciphertext.0;
     let nonce = slice_to_nonce(&ciphertext[0..aead::NONCE_LEN]);
-    let len = ciphertext.len();
-    let ciphertext = &mut ciphertext.expose_mut()[aead::NONCE_LEN..len];
+    let ciphertext = &mut ciphertext.expose_mut()[aead::NONCE_LEN..];
 
     let plaintext = aead_key
-        .open_in_place(nonce, aead::Aad::empty(), ciphertext)
-        .ok()?;
+        .open_in_place(nonce, aead::Aad::from(aad), ciphertext)
+        .map_err(|_| Error::Authentication)?;
 
-    Some(Secret::new(Box::from(plaintext)))
+    Ok(Secret::new(Box::from(plaintext)))
 }
 
 fn slice_to_nonce(slice: &[u8]) -> aead::Nonce {
@@ -98,4 +249,101 @@ mod tests {
         println!("plaintext = {}", str::from_utf8(decoded.expose()).unwrap());
         assert_eq!(message, decoded);
     }
+
+    #[test]
+    fn reject_short_ciphertext() {
+        let key = generate_key().unwrap();
+        let empty = encrypt(Secret::zero(0), key.expose()).unwrap();
+        let min_len = empty.expose().len();
+        assert_eq!(Ciphertext::new(Secret::zero(0)), None);
+        assert_eq!(Ciphertext::new(Secret::zero(min_len - 1)), None);
+        let zeroes = Ciphertext::new(Secret::zero(min_len)).unwrap();
+        assert_eq!(decrypt(zeroes, key.expose()), Err(Error::Authentication));
+        let copy = Ciphertext::new(Secret::new(empty.expose().into())).unwrap();
+        assert_eq!(decrypt(copy, key.expose()), Ok(Secret::zero(0)));
+    }
+
+    #[test]
+    fn associated_data() {
+        let key = generate_key().unwrap();
+        let message = Secret::new((*b"hunter2").into());
+        let ciphertext = encrypt_with_aad(message.clone(), key.expose(), b"irc/password").unwrap();
+        assert_eq!(
+            decrypt_with_aad(ciphertext.clone(), key.expose(), b"irc/password"),
+            Ok(message)
+        );
+        assert_eq!(
+            decrypt_with_aad(ciphertext.clone(), key.expose(), b"bank/password"),
+            Err(Error::Authentication)
+        );
+        assert_eq!(
+            decrypt(ciphertext, key.expose()),
+            Err(Error::Authentication)
+        );
+
+        // wrong key length
+        assert_eq!(
+            encrypt(Secret::zero(1), b"too short").err(),
+            Some(Error::Crypto)
+        );
+    }
+
+    #[test]
+    fn kdf_params() {
+        let salt = b"sixteen byte salt";
+        let pbkdf2 = KdfParams::Pbkdf2HmacSha256 {
+            iterations: PBKDF2_ITERATIONS,
+        };
+        assert_eq!(
+            pbkdf2.derive_key(b"Phoenix", salt),
+            Ok(derive_key(b"Phoenix", salt))
+        );
+
+        let argon2id = KdfParams::Argon2id {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let scrypt = KdfParams::Scrypt {
+            log_n: 4,
+            r: 8,
+            p: 1,
+        };
+        for params in [pbkdf2, argon2id, scrypt, KdfParams::default()] {
+            let key = params.derive_key(b"Phoenix", salt).unwrap();
+            assert_eq!(key.len(), ALGORITHM.key_len());
+            assert_eq!(params.derive_key(b"Phoenix", salt).unwrap(), key);
+            assert_ne!(params.derive_key(b"phoenix", salt).unwrap(), key);
+            assert_ne!(params.derive_key(b"Phoenix", b"other salt").unwrap(), key);
+        }
+        assert_ne!(
+            argon2id.derive_key(b"Phoenix", salt),
+            scrypt.derive_key(b"Phoenix", salt)
+        );
+
+        assert_eq!(
+            KdfParams::default().to_string(),
+            "Argon2id, 19456 KiB memory, 2 iterations, parallelism 1"
+        );
+
+        // out of range parameters
+        let too_little_memory = KdfParams::Argon2id {
+            memory_kib: 1,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert_eq!(
+            too_little_memory.derive_key(b"Phoenix", salt),
+            Err(Error::InvalidKdfParams)
+        );
+        let no_block_size = KdfParams::Scrypt {
+            log_n: 4,
+            r: 0,
+            p: 1,
+        };
+        assert_eq!(
+            no_block_size.derive_key(b"Phoenix", salt),
+            Err(Error::InvalidKdfParams)
+        );
+    }
 }
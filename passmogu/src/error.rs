@@ -0,0 +1,131 @@
+use std::fmt;
+
+/// Everything that can go wrong in passmogu.
+/// Lines and columns are 1-based, columns count bytes. The header is line 1.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Error {
+    /// Data doesn't start with a passmogu header.
+    NotAVault,
+    /// Vault was written by a newer (or unknown) version of the format.
+    UnsupportedVersion(u32),
+    /// Header token at column couldn't be parsed.
+    MalformedHeader { column: usize },
+    /// Header names a key derivation function this library doesn't know.
+    UnknownKdf,
+    /// KDF parameters are out of range for the chosen function.
+    InvalidKdfParams,
+    /// Header's KDF parameter at column would take too much memory or time to unlock.
+    KdfParamTooLarge { column: usize },
+    /// Byte at line, column isn't an uppercase hex digit, or a hex token has odd length.
+    MalformedHex { line: usize, column: usize },
+    /// Row ended early, e.g. a prompt without an answer.
+    TruncatedRow { line: usize },
+    /// Token at line, column is too short to be a ciphertext, or is a sealed vault's blob whose
+    /// size isn't a whole number of padding blocks.
+    TruncatedCiphertext { line: usize, column: usize },
+    /// Field at line, column has a kind that isn't a FieldKind.
+    UnknownFieldKind { line: usize, column: usize },
+    /// Form metadata token at line, column has an unknown key or a malformed value.
+    MalformedMetadata { line: usize, column: usize },
+    /// Unexpected token at line, column after everything that was expected.
+    TrailingData { line: usize, column: usize },
+    /// Vault::load was handed a sealed vault, use EncryptedVault::load.
+    Sealed,
+    /// EncryptedVault::load was handed a vault that isn't sealed, use Vault::load.
+    NotSealed,
+    /// No form with the requested name.
+    FormNotFound,
+    /// A form with the requested name is already present.
+    FormExists,
+    /// Form has no field with the requested prompt.
+    FieldNotFound,
+    /// Form's history has no entry with the requested index.
+    HistoryNotFound,
+    /// Decryption failed: wrong password or key, or a tampered sealed vault.
+    Authentication,
+    /// The index-th field of form, or of its history, didn't decrypt. Its ciphertext was tampered
+    /// with or moved to a different slot, or the key isn't the vault's data key.
+    Corrupt {
+        form: Box<[u8]>,
+        index: usize,
+        history: bool,
+    },
+    /// Passphrase generator was given a dictionary without any words.
+    EmptyDictionary,
+    /// No password satisfies the PasswordPolicy.
+    UnsatisfiablePolicy,
+    /// PasswordPolicy allows passwords longer than generate::MAX_LENGTH.
+    InvalidLength,
+    /// Not a valid otpauth:// URI, or one-time code parameters out of range.
+    InvalidOtp,
+    /// System random number generator failed.
+    Rng,
+    /// Encryption failed for a reason other than the above, e.g. a key of the wrong length.
+    Crypto,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotAVault => write!(f, "not a passmogu vault"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported vault format version {version}")
+            }
+            Error::MalformedHeader { column } => {
+                write!(f, "malformed vault header at line 1, column {column}")
+            }
+            Error::UnknownKdf => write!(f, "unknown key derivation function"),
+            Error::InvalidKdfParams => write!(f, "key derivation parameters out of range"),
+            Error::KdfParamTooLarge { column } => write!(
+                f,
+                "key derivation parameter too large at line 1, column {column}"
+            ),
+            Error::MalformedHex { line, column } => {
+                write!(f, "malformed hex at line {line}, column {column}")
+            }
+            Error::TruncatedRow { line } => write!(f, "truncated row at line {line}"),
+            Error::TruncatedCiphertext { line, column } => {
+                write!(f, "truncated ciphertext at line {line}, column {column}")
+            }
+            Error::UnknownFieldKind { line, column } => {
+                write!(f, "unknown field kind at line {line}, column {column}")
+            }
+            Error::MalformedMetadata { line, column } => {
+                write!(f, "malformed form metadata at line {line}, column {column}")
+            }
+            Error::TrailingData { line, column } => {
+                write!(f, "unexpected data at line {line}, column {column}")
+            }
+            Error::Sealed => write!(f, "vault is sealed"),
+            Error::NotSealed => write!(f, "vault isn't sealed"),
+            Error::FormNotFound => write!(f, "no such form"),
+            Error::FormExists => write!(f, "form already exists"),
+            Error::FieldNotFound => write!(f, "no such field"),
+            Error::HistoryNotFound => write!(f, "no such history entry"),
+            Error::Authentication => write!(f, "wrong password or corrupted data"),
+            Error::Corrupt {
+                form,
+                index,
+                history,
+            } => {
+                let form = String::from_utf8_lossy(form);
+                match history {
+                    false => write!(f, "field {} of form {form} is corrupt", index + 1),
+                    true => write!(f, "history of form {form} is corrupt"),
+                }
+            }
+            Error::EmptyDictionary => write!(f, "dictionary has no words"),
+            Error::UnsatisfiablePolicy => write!(f, "no password satisfies the policy"),
+            Error::InvalidLength => write!(
+                f,
+                "passwords can't be longer than {}",
+                crate::generate::MAX_LENGTH
+            ),
+            Error::InvalidOtp => write!(f, "invalid one-time code parameters"),
+            Error::Rng => write!(f, "random number generator failed"),
+            Error::Crypto => write!(f, "encryption failed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
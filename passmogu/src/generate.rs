@@ -1,14 +1,156 @@
 use aws_lc_rs::rand;
 
+use crate::error::Error;
 use crate::secret::Secret;
+use zeroize::Zeroizing;
 
-// pub fn rand_xkcd(_len: usize, _dictionary: &[&str]) -> Option<Secret> {
-//     todo!()
-// }
+// the bundled word list for rand_xkcd
+mod wordlist;
+
+/// Default dictionary for rand_xkcd: the BIP-39 English word list, 2048 words so each one adds
+/// 11 bits of entropy. That's less than the 12.9 bits of the EFF long list's 7776 words, so a
+/// passphrase needs 7 of these words to be as strong as one of 6 EFF words.
+pub static WORDLIST: &[&str] = &wordlist::WORDS;
+
+/// How rand_xkcd capitalizes the first letter of each word.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Capitalization {
+    /// words are used as they appear in the dictionary
+    #[default]
+    Unchanged,
+    /// every word starts with an uppercase letter
+    Title,
+    /// each word independently starts with an uppercase letter with probability 1/2
+    Random,
+}
+
+/// Knobs for rand_xkcd besides the word count and dictionary.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct XkcdOptions<'a> {
+    /// placed between words
+    pub separator: &'a str,
+    pub capitalization: Capitalization,
+    /// number of random digits appended to one randomly chosen word
+    pub digits: usize,
+}
+
+impl Default for XkcdOptions<'_> {
+    fn default() -> Self {
+        XkcdOptions {
+            separator: "-",
+            capitalization: Capitalization::Unchanged,
+            digits: 0,
+        }
+    }
+}
+
+/// Generate a random passphrase of len words drawn from dictionary, e.g. "correct-horse-battery-staple".
+/// Words are drawn uniformly and independently, duplicate and empty words in dictionary are ignored.
+/// Fails if dictionary has no words, or if the system RNG fails.
+pub fn rand_xkcd(len: usize, dictionary: &[&str], options: &XkcdOptions) -> Result<Secret, Error> {
+    let words = distinct_words(dictionary)?;
+    // draw everything up front so the result can be allocated once
+    let mut chosen = Zeroizing::new(Vec::with_capacity(len));
+    let mut capitalize = Zeroizing::new(Vec::with_capacity(len));
+    for _ in 0..len {
+        chosen.push(rand_below(words.len())?);
+        capitalize.push(match options.capitalization {
+            Capitalization::Unchanged => false,
+            Capitalization::Title => true,
+            Capitalization::Random => rand_below(2)? == 1,
+        });
+    }
+    let digits_after = if len > 0 { rand_below(len)? } else { 0 };
+
+    let mut result_len = chosen.iter().map(|i| words[*i].len()).sum::<usize>();
+    result_len += options.separator.len() * len.saturating_sub(1);
+    result_len += if len > 0 { options.digits } else { 0 };
+    let mut result = Secret::zero(result_len);
+    let mut write_head = 0;
+    for (i, word) in chosen.iter().map(|i| words[*i]).enumerate() {
+        if i > 0 {
+            write_head = write_at(&mut result, write_head, options.separator.as_bytes());
+        }
+        let first = write_head;
+        write_head = write_at(&mut result, write_head, word.as_bytes());
+        if capitalize[i] {
+            result[first] = result[first].to_ascii_uppercase();
+        }
+        if i == digits_after {
+            for _ in 0..options.digits {
+                let digit = b'0' + rand_below(10)? as u8;
+                write_head = write_at(&mut result, write_head, &[digit]);
+            }
+        }
+    }
+    debug_assert_eq!(write_head, result_len);
+    Ok(result)
+}
+
+/// Copies bytes into result at write_head, returns where the next write goes.
+fn write_at(result: &mut Secret, write_head: usize, bytes: &[u8]) -> usize {
+    result[write_head..write_head + bytes.len()].copy_from_slice(bytes);
+    write_head + bytes.len()
+}
+
+/// Entropy in bits of the passphrases rand_xkcd generates with the same arguments.
+/// This is a lower bound: it assumes nothing about how passphrases are parsed back into words,
+/// so random capitalization only counts when every word starts with a lowercase letter and
+/// the digits' position only counts when no word contains a digit.
+pub fn xkcd_entropy(len: usize, dictionary: &[&str], options: &XkcdOptions) -> f64 {
+    let Ok(words) = distinct_words(dictionary) else {
+        return 0.0;
+    };
+    if len == 0 {
+        return 0.0;
+    }
+    let mut bits = len as f64 * (words.len() as f64).log2();
+    let starts_lowercase = |word: &&str| word.as_bytes()[0].is_ascii_lowercase();
+    if options.capitalization == Capitalization::Random && words.iter().all(starts_lowercase) {
+        bits += len as f64;
+    }
+    if options.digits > 0 {
+        bits += options.digits as f64 * 10_f64.log2();
+        let has_digit = |word: &&str| word.bytes().any(|byte| byte.is_ascii_digit());
+        if !words.iter().any(has_digit) {
+            bits += (len as f64).log2();
+        }
+    }
+    bits
+}
+
+/// Sorted, deduplicated, non-empty words of dictionary, so each one is equally likely.
+fn distinct_words<'a>(dictionary: &[&'a str]) -> Result<Vec<&'a str>, Error> {
+    let mut words: Vec<&str> = dictionary
+        .iter()
+        .copied()
+        .filter(|w| !w.is_empty())
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+    if words.is_empty() {
+        return Err(Error::EmptyDictionary);
+    }
+    Ok(words)
+}
+
+/// Uniformly random number in 0..n, rejecting draws that would favor small numbers.
+fn rand_below(n: usize) -> Result<usize, Error> {
+    debug_assert!(n > 0 && n <= u32::MAX as usize);
+    let mut bytes = Zeroizing::new([0_u8; 4]);
+    loop {
+        rand::fill(&mut *bytes).map_err(|_| Error::Rng)?;
+        let draw = u32::from_le_bytes(*bytes) as usize;
+        if draw < greatest_multiple(n, u32::MAX as usize) {
+            return Ok(draw % n);
+        }
+    }
+}
 
 /// Generate a random base62 String (A-Z, a-z, 0-9)
 /// Resulting chars in String are uniformly distributed in the base62 alphabet
-pub fn rand_base62(len: usize) -> Option<Secret> {
+/// Can only fail if the system RNG fails.
+pub fn rand_base62(len: usize) -> Result<Secret, Error> {
     const ALPHABET: [u8; 62] = [
         b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'A', b'B', b'C', b'D', b'E',
         b'F', b'G', b'H', b'I', b'J', b'K', b'L', b'M', b'N', b'O', b'P', b'Q', b'R', b'S', b'T',
@@ -25,7 +167,7 @@ pub fn rand_base62(len: usize) -> Option<Secret> {
 
     loop {
         // refresh with another chunk of random bytes
-        rand::fill(random_bytes.expose_mut()).ok()?;
+        rand::fill(random_bytes.expose_mut()).map_err(|_| Error::Rng)?;
         // encode into base62 by indexing into ALPHABET
         for byte in random_bytes.expose() {
             let index = usize::from(*byte);
@@ -36,10 +178,283 @@ pub fn rand_base62(len: usize) -> Option<Secret> {
             result[write_head] = ALPHABET[index % 62];
             write_head += 1;
             if write_head == len {
-                return Some(result);
+                return Ok(result);
+            }
+        }
+    }
+}
+
+/// Character classes a PasswordPolicy can require or forbid.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CharClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharClass {
+    const ALL: [CharClass; 4] = [
+        CharClass::Lowercase,
+        CharClass::Uppercase,
+        CharClass::Digit,
+        CharClass::Symbol,
+    ];
+
+    fn of(byte: u8) -> CharClass {
+        if byte.is_ascii_lowercase() {
+            CharClass::Lowercase
+        } else if byte.is_ascii_uppercase() {
+            CharClass::Uppercase
+        } else if byte.is_ascii_digit() {
+            CharClass::Digit
+        } else {
+            CharClass::Symbol
+        }
+    }
+}
+
+/// Default symbols, printable ASCII punctuation without quotes, backslash and backtick
+/// which tend to get mangled by shells and web forms.
+pub const DEFAULT_SYMBOLS: &[u8] = b"!#$%&()*+,-./:;<=>?@[]^_{|}~";
+
+/// Characters that are easily confused with each other in many fonts.
+pub const LOOKALIKES: &[u8] = b"0O1Il|";
+
+/// Longest password a PasswordPolicy generates, far beyond what any site accepts.
+pub const MAX_LENGTH: usize = 4096;
+
+/// Describes which passwords a site accepts, e.g. "8 to 16 characters, at least one symbol".
+/// Built up from the default with the builder methods, then generate passwords with generate.
+/// ```
+/// use passmogu::generate::{CharClass, PasswordPolicy};
+/// let password = PasswordPolicy::default()
+///     .length(8, 16)
+///     .require(CharClass::Symbol, 1)
+///     .exclude_lookalikes(true)
+///     .generate()
+///     .unwrap();
+/// assert!((8..=16).contains(&password.len()));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PasswordPolicy {
+    min_len: usize,
+    max_len: usize,
+    /// minimum count of each class, indexed like CharClass::ALL
+    min_counts: [usize; 4],
+    forbidden: [bool; 4],
+    symbols: Vec<u8>,
+    exclude_lookalikes: bool,
+}
+
+impl Default for PasswordPolicy {
+    /// 20 characters from every class, nothing required.
+    fn default() -> Self {
+        PasswordPolicy {
+            min_len: 20,
+            max_len: 20,
+            min_counts: [0; 4],
+            forbidden: [false; 4],
+            symbols: DEFAULT_SYMBOLS.to_vec(),
+            exclude_lookalikes: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Password length is drawn uniformly from min..=max.
+    pub fn length(mut self, min: usize, max: usize) -> Self {
+        self.min_len = min;
+        self.max_len = max;
+        self
+    }
+
+    /// At least count characters of class. A count of 0 removes the requirement.
+    pub fn require(mut self, class: CharClass, count: usize) -> Self {
+        self.min_counts[class as usize] = count;
+        self
+    }
+
+    /// No characters of class at all.
+    pub fn forbid(mut self, class: CharClass) -> Self {
+        self.forbidden[class as usize] = true;
+        self
+    }
+
+    /// Replaces DEFAULT_SYMBOLS. Only printable ASCII that isn't a letter or digit is kept.
+    pub fn symbols(mut self, symbols: &[u8]) -> Self {
+        self.symbols = symbols
+            .iter()
+            .copied()
+            .filter(|byte| byte.is_ascii_graphic() && !byte.is_ascii_alphanumeric())
+            .collect();
+        self.symbols.sort_unstable();
+        self.symbols.dedup();
+        self
+    }
+
+    /// Leaves out LOOKALIKES.
+    pub fn exclude_lookalikes(mut self, exclude: bool) -> Self {
+        self.exclude_lookalikes = exclude;
+        self
+    }
+
+    /// Every character this policy may generate.
+    fn alphabet(&self) -> Vec<u8> {
+        let mut alphabet: Vec<u8> = (b'a'..=b'z')
+            .chain(b'A'..=b'Z')
+            .chain(b'0'..=b'9')
+            .collect();
+        alphabet.extend(&self.symbols);
+        alphabet.retain(|byte| {
+            let lookalike = self.exclude_lookalikes && LOOKALIKES.contains(byte);
+            !self.forbidden[CharClass::of(*byte) as usize] && !lookalike
+        });
+        alphabet
+    }
+
+    /// Fails with UnsatisfiablePolicy if no password fits, e.g. a required class is forbidden or
+    /// the minimum counts add up to more than the maximum length, or with InvalidLength if the
+    /// maximum length is over MAX_LENGTH.
+    fn check(&self, alphabet: &[u8]) -> Result<(), Error> {
+        if self.max_len > MAX_LENGTH {
+            return Err(Error::InvalidLength);
+        }
+        let required: usize = self.min_counts.iter().sum();
+        let mut satisfiable = self.min_len <= self.max_len
+            && required <= self.max_len
+            && (self.max_len == 0 || !alphabet.is_empty());
+        for class in CharClass::ALL {
+            let available = alphabet.iter().any(|byte| CharClass::of(*byte) == class);
+            satisfiable &= self.min_counts[class as usize] == 0 || available;
+        }
+        if !satisfiable {
+            return Err(Error::UnsatisfiablePolicy);
+        }
+        Ok(())
+    }
+
+    /// Generates a password satisfying the policy.
+    /// Among passwords of the chosen length, every one satisfying the policy is equally likely:
+    /// the number of characters of each class is drawn in proportion to how many such passwords
+    /// have that many, the characters are drawn uniformly from their class, then shuffled.
+    /// Fails if the policy is unsatisfiable, or if the system RNG fails.
+    pub fn generate(&self) -> Result<Secret, Error> {
+        let alphabet = self.alphabet();
+        self.check(&alphabet)?;
+        let required: usize = self.min_counts.iter().sum();
+        // lengths that can't fit every required character would never be accepted
+        let min_len = self.min_len.max(required);
+        let len = min_len + rand_below(self.max_len - min_len + 1)?;
+        let mut result = Secret::zero(len);
+        if required == 0 {
+            // every string over the alphabet is allowed
+            for i in 0..len {
+                result[i] = alphabet[rand_below(alphabet.len())?];
+            }
+            return Ok(result);
+        }
+        let classes = CharClass::ALL.map(|class| {
+            let in_class = alphabet
+                .iter()
+                .copied()
+                .filter(|byte| CharClass::of(*byte) == class);
+            in_class.collect::<Vec<u8>>()
+        });
+        let counts = self.class_counts(&classes, len)?;
+        let mut write_head = 0;
+        for (class, count) in classes.iter().zip(counts.iter()) {
+            for _ in 0..*count {
+                result[write_head] = class[rand_below(class.len())?];
+                write_head += 1;
             }
         }
+        // Fisher-Yates
+        for i in (1..len).rev() {
+            result.expose_mut().swap(i, rand_below(i + 1)?);
+        }
+        Ok(result)
+    }
+
+    /// How many characters of each class (the bytes of alphabet in classes, indexed like
+    /// CharClass::ALL) a password of len gets. Each choice is weighted by the number of passwords
+    /// satisfying the policy that have those counts, so shuffled passwords stay uniform.
+    fn class_counts(
+        &self,
+        classes: &[Vec<u8>; 4],
+        len: usize,
+    ) -> Result<Zeroizing<[usize; 4]>, Error> {
+        // the counts are astronomically large, so they are kept as natural logarithms
+        let ln_factorial: Vec<f64> = (0..=len)
+            .scan(0.0, |sum: &mut f64, n| {
+                *sum += (n.max(1) as f64).ln();
+                Some(*sum)
+            })
+            .collect();
+        let ln_choose =
+            |n: usize, k: usize| ln_factorial[n] - ln_factorial[k] - ln_factorial[n - k];
+        // ln of (class size)^count, with 0^0 = 1
+        let ln_power = |size: usize, count: usize| match (size, count) {
+            (_, 0) => 0.0,
+            (0, _) => f64::NEG_INFINITY,
+            _ => count as f64 * (size as f64).ln(),
+        };
+        // ways[i][n]: passwords of length n made of classes i.., meeting their minimum counts
+        let mut ways = vec![vec![f64::NEG_INFINITY; len + 1]; 5];
+        ways[4][0] = 0.0;
+        // ln of the number of passwords of length n where class i has count characters
+        let term = |ways: &[Vec<f64>], i: usize, n: usize, count: usize| {
+            ln_choose(n, count) + ln_power(classes[i].len(), count) + ways[i + 1][n - count]
+        };
+        for i in (0..4).rev() {
+            for n in 0..=len {
+                let terms: Vec<f64> = (self.min_counts[i]..=n)
+                    .map(|count| term(&ways, i, n, count))
+                    .collect();
+                ways[i][n] = ln_sum(&terms);
+            }
+        }
+        let mut counts = Zeroizing::new([0; 4]);
+        let mut remaining = len;
+        for i in 0..4 {
+            let total = ways[i][remaining];
+            let draw = rand_unit()?;
+            let mut cumulative = 0.0;
+            let mut chosen = None;
+            for count in self.min_counts[i]..=remaining {
+                let probability = (term(&ways, i, remaining, count) - total).exp();
+                if probability > 0.0 {
+                    // rounding can leave the cumulative sum short of 1, the last one takes that
+                    chosen = Some(count);
+                }
+                cumulative += probability;
+                if draw < cumulative {
+                    break;
+                }
+            }
+            // check guarantees some count is possible
+            counts[i] = chosen.ok_or(Error::UnsatisfiablePolicy)?;
+            remaining -= counts[i];
+        }
+        debug_assert_eq!(remaining, 0);
+        Ok(counts)
+    }
+}
+
+/// ln(sum of exp(x) for x in terms), without overflowing.
+fn ln_sum(terms: &[f64]) -> f64 {
+    let max = terms.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
     }
+    max + terms.iter().map(|x| (x - max).exp()).sum::<f64>().ln()
+}
+
+/// Uniformly random number in [0, 1), with 53 random bits.
+fn rand_unit() -> Result<f64, Error> {
+    let mut bytes = Zeroizing::new([0_u8; 8]);
+    rand::fill(&mut *bytes).map_err(|_| Error::Rng)?;
+    Ok((u64::from_le_bytes(*bytes) >> 11) as f64 / (1_u64 << 53) as f64)
 }
 
 /// greatest multiple of 'number' strictly less then 'upper_limit'
@@ -86,4 +501,265 @@ mod tests {
             assert!((frequency - EXPECT).abs() < TOLERANCE);
         }
     }
+
+    #[test]
+    fn basic_xkcd() {
+        let options = XkcdOptions::default();
+        let passphrase = rand_xkcd(6, WORDLIST, &options).unwrap();
+        let passphrase = str::from_utf8(passphrase.expose()).unwrap();
+        println!("passphrase: {passphrase}");
+        let words: Vec<&str> = passphrase.split('-').collect();
+        assert_eq!(words.len(), 6);
+        assert!(words.iter().all(|word| WORDLIST.contains(word)));
+
+        let options = XkcdOptions {
+            separator: " ",
+            capitalization: Capitalization::Title,
+            digits: 3,
+        };
+        let passphrase = rand_xkcd(4, &["correct", "horse"], &options).unwrap();
+        let passphrase = str::from_utf8(passphrase.expose()).unwrap();
+        println!("passphrase: {passphrase}");
+        let words: Vec<&str> = passphrase.split(' ').collect();
+        assert_eq!(words.len(), 4);
+        for word in words {
+            let word = word.trim_end_matches(|c: char| c.is_ascii_digit());
+            assert!(word == "Correct" || word == "Horse");
+        }
+        let digits = passphrase.bytes().filter(u8::is_ascii_digit).count();
+        assert_eq!(digits, 3);
+
+        assert!(rand_xkcd(0, WORDLIST, &options).unwrap().is_empty());
+        assert_eq!(
+            rand_xkcd(4, &[], &options).err(),
+            Some(Error::EmptyDictionary)
+        );
+        assert_eq!(
+            rand_xkcd(4, &[""], &options).err(),
+            Some(Error::EmptyDictionary)
+        );
+    }
+
+    #[test]
+    fn xkcd_entropy_bits() {
+        assert_eq!(WORDLIST.len(), 2048);
+        let mut options = XkcdOptions::default();
+        assert_eq!(xkcd_entropy(6, WORDLIST, &options), 66.0);
+        // duplicates don't count
+        assert_eq!(xkcd_entropy(4, &["a", "b", "a", "b"], &options), 4.0);
+        assert_eq!(xkcd_entropy(4, &[], &options), 0.0);
+
+        options.capitalization = Capitalization::Title;
+        assert_eq!(xkcd_entropy(6, WORDLIST, &options), 66.0);
+        options.capitalization = Capitalization::Random;
+        assert_eq!(xkcd_entropy(6, WORDLIST, &options), 72.0);
+        // "A" can't be capitalized, so random capitalization may not add anything
+        assert_eq!(xkcd_entropy(4, &["A", "b"], &options), 4.0);
+
+        options.capitalization = Capitalization::Unchanged;
+        options.digits = 2;
+        let expect = 8.0 * 11.0 + 2.0 * 10_f64.log2() + 3.0;
+        assert!((xkcd_entropy(8, WORDLIST, &options) - expect).abs() < 1e-9);
+        // can't tell where the digits were appended
+        let expect = 8.0 + 2.0 * 10_f64.log2();
+        assert!((xkcd_entropy(8, &["a1", "b"], &options) - expect).abs() < 1e-9);
+    }
+
+    #[test]
+    /// detects statistical bias in word choice, capitalization and digits
+    fn xkcd_word_distribution() {
+        let dictionary = ["alpha", "bravo", "charlie", "delta", "echo"];
+        let options = XkcdOptions {
+            separator: " ",
+            capitalization: Capitalization::Random,
+            digits: 1,
+        };
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut digits: HashMap<u8, usize> = HashMap::new();
+        let mut total = 0;
+        for _ in 0..10000 {
+            let passphrase = rand_xkcd(10, &dictionary, &options).unwrap();
+            let passphrase = str::from_utf8(passphrase.expose()).unwrap();
+            for word in passphrase.split(' ') {
+                let trimmed = word.trim_end_matches(|c: char| c.is_ascii_digit());
+                if let Some(digit) = word.bytes().last().filter(u8::is_ascii_digit) {
+                    *digits.entry(digit).or_default() += 1;
+                }
+                *counts.entry(trimmed.to_string()).or_default() += 1;
+                total += 1;
+            }
+        }
+        assert_eq!(counts.len(), 2 * dictionary.len());
+        for v in counts.values() {
+            let frequency = *v as f64 / total as f64;
+            const EXPECT: f64 = 1_f64 / 10_f64;
+            const TOLERANCE: f64 = 0.005;
+            assert!((frequency - EXPECT).abs() < TOLERANCE);
+        }
+        assert_eq!(digits.len(), 10);
+        for v in digits.values() {
+            let frequency = *v as f64 / 10000_f64;
+            const EXPECT: f64 = 1_f64 / 10_f64;
+            const TOLERANCE: f64 = 0.01;
+            assert!((frequency - EXPECT).abs() < TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn basic_policy() {
+        let default = PasswordPolicy::default().generate().unwrap();
+        assert_eq!(default.len(), 20);
+
+        let policy = PasswordPolicy::default()
+            .length(8, 16)
+            .require(CharClass::Digit, 2)
+            .require(CharClass::Symbol, 1)
+            .forbid(CharClass::Uppercase)
+            .symbols(b"@#ab")
+            .exclude_lookalikes(true);
+        for _ in 0..100 {
+            let password = policy.generate().unwrap();
+            let password = password.expose();
+            assert!((8..=16).contains(&password.len()));
+            assert!(password.iter().filter(|c| c.is_ascii_digit()).count() >= 2);
+            assert!(password.iter().any(|c| b"@#".contains(c)));
+            assert!(password.iter().all(|c| !c.is_ascii_uppercase()));
+            assert!(
+                password
+                    .iter()
+                    .all(|c| c.is_ascii_alphanumeric() || b"@#".contains(c))
+            );
+            assert!(password.iter().all(|c| !LOOKALIKES.contains(c)));
+        }
+
+        // minimum counts can fill the whole password
+        let digits = PasswordPolicy::default()
+            .length(0, 4)
+            .require(CharClass::Digit, 4)
+            .generate()
+            .unwrap();
+        assert_eq!(digits.len(), 4);
+        assert!(digits.expose().iter().all(u8::is_ascii_digit));
+
+        // and nearly fill it, where drawing whole passwords until one fits would take forever
+        let symbols = PasswordPolicy::default()
+            .length(20, 20)
+            .require(CharClass::Symbol, 19)
+            .require(CharClass::Digit, 1);
+        for _ in 0..100 {
+            let password = symbols.generate().unwrap();
+            let password = password.expose();
+            assert_eq!(password.iter().filter(|c| c.is_ascii_digit()).count(), 1);
+            assert!(
+                password
+                    .iter()
+                    .filter(|c| !c.is_ascii_digit())
+                    .all(|c| DEFAULT_SYMBOLS.contains(c))
+            );
+        }
+
+        let empty = PasswordPolicy::default().length(0, 0).generate().unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn reject_unsatisfiable_policy() {
+        let reject = [
+            PasswordPolicy::default().length(16, 8),
+            PasswordPolicy::default()
+                .length(4, 4)
+                .require(CharClass::Digit, 3)
+                .require(CharClass::Symbol, 2),
+            PasswordPolicy::default()
+                .require(CharClass::Symbol, 1)
+                .forbid(CharClass::Symbol),
+            PasswordPolicy::default()
+                .require(CharClass::Symbol, 1)
+                .symbols(b"abc"),
+            PasswordPolicy::default()
+                .forbid(CharClass::Lowercase)
+                .forbid(CharClass::Uppercase)
+                .forbid(CharClass::Digit)
+                .forbid(CharClass::Symbol),
+        ];
+        for policy in reject {
+            assert_eq!(
+                policy.generate().err(),
+                Some(Error::UnsatisfiablePolicy),
+                "{policy:?}"
+            );
+        }
+        let longest = PasswordPolicy::default().length(MAX_LENGTH, MAX_LENGTH);
+        assert_eq!(longest.generate().unwrap().len(), MAX_LENGTH);
+        assert_eq!(
+            longest.length(8, MAX_LENGTH + 1).generate().err(),
+            Some(Error::InvalidLength)
+        );
+    }
+
+    #[test]
+    /// detects statistical bias in policy string gen
+    fn policy_char_distribution() {
+        let policy = PasswordPolicy::default()
+            .length(100, 100)
+            .symbols(b"!?")
+            .exclude_lookalikes(true);
+        let alphabet_len = 62 + 2 - 5;
+        let mut counts: HashMap<u8, usize> = HashMap::new();
+        let mut total = 0;
+        for _ in 0..5000 {
+            let string = policy.generate().unwrap();
+            for c in string.expose().iter() {
+                *counts.entry(*c).or_default() += 1;
+                total += 1;
+            }
+        }
+        assert_eq!(counts.len(), alphabet_len);
+        for v in counts.values() {
+            let frequency = *v as f64 / total as f64;
+            let expect = 1_f64 / alphabet_len as f64;
+            const TOLERANCE: f64 = 0.001;
+            assert!((frequency - expect).abs() < TOLERANCE);
+        }
+    }
+
+    #[test]
+    /// every password satisfying the policy is equally likely, requirements don't skew it
+    fn policy_password_distribution() {
+        let policy = PasswordPolicy::default()
+            .length(2, 2)
+            .require(CharClass::Digit, 1)
+            .forbid(CharClass::Uppercase)
+            .forbid(CharClass::Symbol);
+        // 36 * 36 strings of lowercase letters and digits, minus 26 * 26 without a digit
+        const VALID: usize = 36 * 36 - 26 * 26;
+        let mut counts: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut total = 0;
+        for _ in 0..100000 {
+            let string = policy.generate().unwrap();
+            *counts.entry(string.expose().to_vec()).or_default() += 1;
+            total += 1;
+        }
+        assert_eq!(counts.len(), VALID);
+        for v in counts.values() {
+            let frequency = *v as f64 / total as f64;
+            const EXPECT: f64 = 1_f64 / VALID as f64;
+            const TOLERANCE: f64 = 0.0006;
+            assert!((frequency - EXPECT).abs() < TOLERANCE);
+        }
+
+        // and each allowed length is equally likely
+        let policy = PasswordPolicy::default().length(8, 12);
+        let mut lengths: HashMap<usize, usize> = HashMap::new();
+        for _ in 0..10000 {
+            *lengths.entry(policy.generate().unwrap().len()).or_default() += 1;
+        }
+        assert_eq!(lengths.len(), 5);
+        for v in lengths.values() {
+            let frequency = *v as f64 / 10000_f64;
+            const EXPECT: f64 = 1_f64 / 5_f64;
+            const TOLERANCE: f64 = 0.02;
+            assert!((frequency - EXPECT).abs() < TOLERANCE);
+        }
+    }
 }
@@ -0,0 +1,415 @@
+use crate::encrypt::{self, Ciphertext, KdfParams};
+use crate::error::Error;
+use crate::hex::Hex;
+use crate::secret::Secret;
+use crate::tsv::{Token, Tokens};
+use aws_lc_rs::rand;
+use std::num::NonZeroU32;
+
+/// Every serialized vault starts with these bytes.
+const MAGIC: &[u8] = b"PASSMOGU";
+/// Version of the serialized vault format written by this library, the only one it loads.
+pub const FORMAT_VERSION: u32 = 6;
+/// Length of the random per-vault salt in bytes.
+const SALT_LEN: usize = 32;
+/// Most memory a KDF may use, in bytes. Along with the limits below, keeps a crafted header from
+/// making unlocking allocate or compute without bound.
+const MAX_KDF_MEMORY: u64 = 4 << 30;
+const MAX_PBKDF2_ITERATIONS: u32 = 100_000_000;
+const MAX_ARGON2_ITERATIONS: u32 = 64;
+const MAX_SCRYPT_LOG_N: u8 = 24;
+/// Limit on Argon2id's and scrypt's parallelism.
+const MAX_PARALLELISM: u32 = 64;
+
+/// Header is the first line of a serialized vault. It records everything needed to recover the
+/// vault's data key from the master password alone, so frontends don't have to store the salt elsewhere.
+/// The format is "PASSMOGU\tversion\tkdf\tsalt\twrapped_key\n" with salt and wrapped_key hex encoded.
+/// The kdf token names the function followed by its parameters, e.g. "argon2id:m=19456,t=2,p=1".
+///
+/// Vault data is encrypted with a random data key. The key derived from the master password only
+/// encrypts ("wraps") the data key, so changing the master password only rewraps wrapped_key.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Header {
+    pub kdf: KdfParams,
+    pub salt: Box<[u8]>,
+    pub wrapped_key: Ciphertext,
+}
+
+impl Header {
+    /// Creates a header for a new vault with a fresh random salt and data key.
+    /// Returns the header along with the unwrapped data key.
+    /// Fails if the system RNG fails or the KDF parameters are out of range.
+    pub fn new(kdf: KdfParams, password: &[u8]) -> Result<(Self, Secret), Error> {
+        let data_key = encrypt::generate_key()?;
+        let header = Self::wrap(kdf, password, &data_key)?;
+        Ok((header, data_key))
+    }
+
+    /// Creates a header with a fresh random salt that wraps an existing data key under password.
+    /// Fails with Error::InvalidKdfParams for parameters load would reject as too large.
+    pub fn wrap(kdf: KdfParams, password: &[u8], data_key: &Secret) -> Result<Self, Error> {
+        if over_limit(&kdf).is_some() {
+            return Err(Error::InvalidKdfParams);
+        }
+        let mut salt = vec![0_u8; SALT_LEN].into_boxed_slice();
+        rand::fill(&mut salt).map_err(|_| Error::Rng)?;
+        let wrapping_key = kdf.derive_key(password, &salt)?;
+        let wrapped_key = encrypt::encrypt(data_key.clone(), wrapping_key.expose())?;
+        Ok(Header {
+            kdf,
+            salt,
+            wrapped_key,
+        })
+    }
+
+    /// Re-derives the key encryption key from the master password.
+    /// Fails if the KDF parameters are out of range.
+    pub fn derive_key(&self, password: &[u8]) -> Result<Secret, Error> {
+        self.kdf.derive_key(password, &self.salt)
+    }
+
+    /// Recovers the data key from the master password.
+    /// Fails with Error::Authentication if the password is wrong.
+    pub fn unlock(&self, password: &[u8]) -> Result<Secret, Error> {
+        let wrapping_key = self.derive_key(password)?;
+        encrypt::decrypt(self.wrapped_key.clone(), wrapping_key.expose())
+    }
+
+    /// Serializes header into a single tsv line, including the trailing '\n'.
+    pub(crate) fn dump(&self) -> Vec<u8> {
+        let mut line = Vec::new();
+        line.extend(MAGIC);
+        line.push(b'\t');
+        line.extend(FORMAT_VERSION.to_string().as_bytes());
+        line.push(b'\t');
+        line.extend(dump_kdf(&self.kdf).as_bytes());
+        line.push(b'\t');
+        line.extend(Hex::encode(&self.salt).as_slice());
+        line.push(b'\t');
+        line.extend(Hex::encode(self.wrapped_key.expose()).as_slice());
+        line.push(b'\n');
+        line
+    }
+
+    /// Deserializes header from a single tsv line (without the trailing '\n').
+    pub(crate) fn load(line: &[u8]) -> Result<Self, Error> {
+        let mut tokens = Tokens::new(1, line);
+        if tokens.next().map(|token| token.bytes) != Some(MAGIC) {
+            return Err(Error::NotAVault);
+        }
+        let version = parse_decimal(tokens.require()?)?;
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let kdf = load_kdf(tokens.require()?)?;
+        let salt = tokens.require()?.decode_hex()?.expose().into();
+        let wrapped_key = tokens.require()?.decode_ciphertext()?;
+        if let Some(token) = tokens.next() {
+            return Err(Error::MalformedHeader {
+                column: token.column,
+            });
+        }
+        Ok(Header {
+            kdf,
+            salt,
+            wrapped_key,
+        })
+    }
+}
+
+fn dump_kdf(kdf: &KdfParams) -> String {
+    match *kdf {
+        KdfParams::Pbkdf2HmacSha256 { iterations } => format!("pbkdf2-sha256:i={iterations}"),
+        KdfParams::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => format!("argon2id:m={memory_kib},t={iterations},p={parallelism}"),
+        KdfParams::Scrypt { log_n, r, p } => format!("scrypt:ln={log_n},r={r},p={p}"),
+    }
+}
+
+/// Key of the first parameter of kdf above its limit, None if they're all within them.
+/// scrypt uses 128 * r * 2^log_n bytes of memory, so r is limited along with log_n.
+fn over_limit(kdf: &KdfParams) -> Option<&'static str> {
+    match *kdf {
+        KdfParams::Pbkdf2HmacSha256 { iterations } => {
+            (iterations.get() > MAX_PBKDF2_ITERATIONS).then_some("i")
+        }
+        KdfParams::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => {
+            if u64::from(memory_kib) * 1024 > MAX_KDF_MEMORY {
+                Some("m")
+            } else if iterations > MAX_ARGON2_ITERATIONS {
+                Some("t")
+            } else {
+                (parallelism > MAX_PARALLELISM).then_some("p")
+            }
+        }
+        KdfParams::Scrypt { log_n, r, p } => {
+            if log_n > MAX_SCRYPT_LOG_N {
+                Some("ln")
+            } else if (128 * u64::from(r)) << log_n > MAX_KDF_MEMORY {
+                Some("r")
+            } else {
+                (p > MAX_PARALLELISM).then_some("p")
+            }
+        }
+    }
+}
+
+/// Parses "name:key=value,key=value". Parameters must appear in the order dump_kdf writes them.
+/// Fails with Error::KdfParamTooLarge at the first value over its limit, see over_limit.
+fn load_kdf(token: Token) -> Result<KdfParams, Error> {
+    let malformed = Error::MalformedHeader {
+        column: token.column,
+    };
+    let text = str::from_utf8(token.bytes).map_err(|_| malformed.clone())?;
+    let (name, params) = text.split_once(':').unwrap_or((text, ""));
+    let mut params = params.split(',');
+    // where each value starts, for KdfParamTooLarge
+    let mut columns = Vec::new();
+    let mut column = token.column + name.len() + 1;
+    let mut param = |key: &'static str| -> Result<u32, Error> {
+        let (k, v) = params
+            .next()
+            .and_then(|param| param.split_once('='))
+            .ok_or(malformed.clone())?;
+        if k != key {
+            return Err(malformed.clone());
+        }
+        columns.push((key, column + k.len() + 1));
+        column += k.len() + v.len() + 2;
+        v.parse().map_err(|_| malformed.clone())
+    };
+    let kdf = match name {
+        "pbkdf2-sha256" => KdfParams::Pbkdf2HmacSha256 {
+            iterations: NonZeroU32::new(param("i")?).ok_or(Error::InvalidKdfParams)?,
+        },
+        "argon2id" => KdfParams::Argon2id {
+            memory_kib: param("m")?,
+            iterations: param("t")?,
+            parallelism: param("p")?,
+        },
+        "scrypt" => KdfParams::Scrypt {
+            log_n: param("ln")?
+                .try_into()
+                .map_err(|_| Error::InvalidKdfParams)?,
+            r: param("r")?,
+            p: param("p")?,
+        },
+        _ => return Err(Error::UnknownKdf),
+    };
+    if params.next().is_some() {
+        return Err(malformed);
+    }
+    if let Some(key) = over_limit(&kdf) {
+        let (_, column) = columns.into_iter().find(|(k, _)| *k == key).unwrap();
+        return Err(Error::KdfParamTooLarge { column });
+    }
+    Ok(kdf)
+}
+
+fn parse_decimal(token: Token) -> Result<u32, Error> {
+    str::from_utf8(token.bytes)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or(Error::MalformedHeader {
+            column: token.column,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::FAST_KDF;
+
+    #[test]
+    fn header_round_trip() {
+        let (header, _) = Header::new(KdfParams::default(), b"hunter2").unwrap();
+        assert_eq!(header.salt.len(), SALT_LEN);
+        let line = header.dump();
+        assert!(line.starts_with(b"PASSMOGU\t6\targon2id:m=19456,t=2,p=1\t"));
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert_eq!(Header::load(&line[..line.len() - 1]), Ok(header));
+
+        for kdf in [
+            FAST_KDF,
+            KdfParams::Scrypt {
+                log_n: 4,
+                r: 8,
+                p: 1,
+            },
+        ] {
+            let (header, _) = Header::new(kdf, b"hunter2").unwrap();
+            let line = header.dump();
+            assert_eq!(Header::load(&line[..line.len() - 1]), Ok(header));
+        }
+    }
+
+    #[test]
+    fn salts_and_keys_are_random() {
+        let (first, first_key) = Header::new(FAST_KDF, b"hunter2").unwrap();
+        let (second, second_key) = Header::new(FAST_KDF, b"hunter2").unwrap();
+        assert_ne!(first.salt, second.salt);
+        assert_ne!(first_key, second_key);
+    }
+
+    #[test]
+    fn unwrap_data_key() {
+        let (header, data_key) = Header::new(FAST_KDF, b"hunter2").unwrap();
+        assert_eq!(header.unlock(b"hunter2"), Ok(data_key.clone()));
+        assert_eq!(header.unlock(b"hunter3"), Err(Error::Authentication));
+        // the data key isn't the password derived key
+        assert_ne!(header.derive_key(b"hunter2"), Ok(data_key.clone()));
+
+        // rewrapping under a new password keeps the same data key
+        let rewrapped = Header::wrap(FAST_KDF, b"correct horse", &data_key).unwrap();
+        assert_ne!(rewrapped.salt, header.salt);
+        assert_eq!(rewrapped.unlock(b"correct horse"), Ok(data_key));
+        assert_eq!(rewrapped.unlock(b"hunter2"), Err(Error::Authentication));
+    }
+
+    #[test]
+    fn limit_kdf_params() {
+        let wrapped_key = "00".repeat(28);
+        for kdf in [
+            "argon2id:m=4194304,t=64,p=64",
+            "scrypt:ln=22,r=8,p=64",
+            "pbkdf2-sha256:i=100000000",
+        ] {
+            let line = format!("PASSMOGU\t6\t{kdf}\t00\t{wrapped_key}");
+            assert!(Header::load(line.as_bytes()).is_ok());
+        }
+        // nothing is written that couldn't be loaded again
+        let too_large = KdfParams::Argon2id {
+            memory_kib: 4 * 1024 * 1024 + 1,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert_eq!(
+            Header::new(too_large, b"hunter2"),
+            Err(Error::InvalidKdfParams)
+        );
+    }
+
+    #[test]
+    fn reject_malformed_header() {
+        let reject = [
+            (&b""[..], Error::NotAVault),
+            (b"PASSMOGU", Error::TruncatedRow { line: 1 }),
+            (
+                b"NOTMOGU\t6\tpbkdf2-sha256:i=300000\t00\t00",
+                Error::NotAVault,
+            ),
+            (
+                b"PASSMOGU\t999\tpbkdf2-sha256:i=300000\t00\t00",
+                Error::UnsupportedVersion(999),
+            ),
+            (
+                b"PASSMOGU\t5\tpbkdf2-sha256:i=300000\t00\t00",
+                Error::UnsupportedVersion(5),
+            ),
+            (
+                b"PASSMOGU\t1\tpbkdf2-sha256\t300000\t00",
+                Error::UnsupportedVersion(1),
+            ),
+            (
+                b"PASSMOGU\tthree\tpbkdf2-sha256:i=300000\t00\t00",
+                Error::MalformedHeader { column: 10 },
+            ),
+            (b"PASSMOGU\t6\tmd5:i=300000\t00\t00", Error::UnknownKdf),
+            (
+                b"PASSMOGU\t6\tpbkdf2-sha256:i=0\t00\t00",
+                Error::InvalidKdfParams,
+            ),
+            (
+                b"PASSMOGU\t6\tpbkdf2-sha256\t00\t00",
+                Error::MalformedHeader { column: 12 },
+            ),
+            (
+                b"PASSMOGU\t6\tpbkdf2-sha256:i=1,i=1\t00\t00",
+                Error::MalformedHeader { column: 12 },
+            ),
+            (
+                b"PASSMOGU\t6\targon2id:t=2,m=19456,p=1\t00\t00",
+                Error::MalformedHeader { column: 12 },
+            ),
+            (
+                b"PASSMOGU\t6\tscrypt:ln=256,r=8,p=1\t00\t00",
+                Error::InvalidKdfParams,
+            ),
+            (
+                b"PASSMOGU\t6\targon2id:m=4194305,t=2,p=1\t00\t00",
+                Error::KdfParamTooLarge { column: 23 },
+            ),
+            (
+                b"PASSMOGU\t6\targon2id:m=19456,t=65,p=1\t00\t00",
+                Error::KdfParamTooLarge { column: 31 },
+            ),
+            (
+                b"PASSMOGU\t6\tscrypt:ln=25,r=8,p=1\t00\t00",
+                Error::KdfParamTooLarge { column: 22 },
+            ),
+            // 4.5 GiB
+            (
+                b"PASSMOGU\t6\tscrypt:ln=22,r=9,p=1\t00\t00",
+                Error::KdfParamTooLarge { column: 27 },
+            ),
+            (
+                b"PASSMOGU\t6\tpbkdf2-sha256:i=300000\tzz\t00",
+                Error::MalformedHex {
+                    line: 1,
+                    column: 35,
+                },
+            ),
+            (
+                b"PASSMOGU\t6\tpbkdf2-sha256:i=300000\t00\t00zz",
+                Error::MalformedHex {
+                    line: 1,
+                    column: 40,
+                },
+            ),
+            (
+                b"PASSMOGU\t6\tpbkdf2-sha256:i=300000\t00",
+                Error::TruncatedRow { line: 1 },
+            ),
+            // missing or too short to be a wrapped key
+            (
+                b"PASSMOGU\t6\tpbkdf2-sha256:i=300000\t00\t",
+                Error::TruncatedCiphertext {
+                    line: 1,
+                    column: 38,
+                },
+            ),
+            (
+                b"PASSMOGU\t6\tpbkdf2-sha256:i=300000\t00\t00",
+                Error::TruncatedCiphertext {
+                    line: 1,
+                    column: 38,
+                },
+            ),
+        ];
+        for (line, error) in reject {
+            assert_eq!(
+                Header::load(line),
+                Err(error),
+                "{}",
+                String::from_utf8_lossy(line)
+            );
+        }
+        let wrapped_key = "00".repeat(28);
+        let line = format!("PASSMOGU\t6\tpbkdf2-sha256:i=300000\t00\t{wrapped_key}");
+        assert!(Header::load(line.as_bytes()).is_ok());
+        let trailing = format!("{line}\t00");
+        assert_eq!(
+            Header::load(trailing.as_bytes()),
+            Err(Error::MalformedHeader {
+                column: line.len() + 2
+            })
+        );
+    }
+}
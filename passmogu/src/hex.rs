@@ -8,13 +8,18 @@ pub(crate) struct Hex {
 }
 
 impl Hex {
-    pub(crate) fn new(bytes: &[u8]) -> Option<Self> {
-        for byte in bytes {
+    /// Validates bytes as a hex string.
+    /// On failure, returns the offset of the first invalid byte (bytes.len() if the length is odd).
+    pub(crate) fn new(bytes: &[u8]) -> Result<Self, usize> {
+        for (i, byte) in bytes.iter().enumerate() {
             if !byte.is_ascii_digit() && !(b'A'..=b'F').contains(byte) {
-                return None;
+                return Err(i);
             }
         }
-        Some(Self {
+        if !bytes.len().is_multiple_of(2) {
+            return Err(bytes.len());
+        }
+        Ok(Self {
             str: Secret::new(Box::from(bytes)),
         })
     }
@@ -79,4 +84,13 @@ mod tests {
         let decoded = hex.decode();
         assert_eq!(bytes, decoded.expose());
     }
+
+    #[test]
+    fn reject_invalid_hex() {
+        assert!(Hex::new(b"").is_ok());
+        assert!(Hex::new(b"0123456789ABCDEF").is_ok());
+        assert_eq!(Hex::new(b"00a0").err(), Some(2));
+        assert_eq!(Hex::new(b"00\t0").err(), Some(2));
+        assert_eq!(Hex::new(b"000").err(), Some(3));
+    }
 }
@@ -1,10 +1,27 @@
 // password based encryption
 pub mod encrypt;
+// error type shared by the whole library
+pub mod error;
 // password generation
 pub mod generate;
+// vault file header: format version and key derivation parameters
+pub mod header;
+// one-time codes for two factor authentication (TOTP and HOTP)
+pub mod otp;
+// reading passwords from the terminal without echoing them
+pub mod prompt;
+// finding forms by name, prompt, URL or tag
+pub mod search;
 // zeroizing string type
 pub mod secret;
 // interface for serializing login form data
 pub mod vault;
 // hex encoding / decoding
 mod hex;
+// shared fixtures for unit tests
+#[cfg(test)]
+mod test_util;
+// tokenizing tsv with positions for error messages
+mod tsv;
+
+pub use error::Error;
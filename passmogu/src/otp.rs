@@ -0,0 +1,475 @@
+use crate::error::Error;
+use crate::secret::Secret;
+use crate::vault::{Field, Vault, seal_form};
+use aws_lc_rs::hmac;
+use std::fmt;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Hash function behind the HMAC, SHA1 unless the otpauth:// URI says otherwise.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn hmac(self) -> hmac::Algorithm {
+        match self {
+            Algorithm::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            Algorithm::Sha256 => hmac::HMAC_SHA256,
+            Algorithm::Sha512 => hmac::HMAC_SHA512,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    /// The name used in otpauth:// URIs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Sha1 => write!(f, "SHA1"),
+            Algorithm::Sha256 => write!(f, "SHA256"),
+            Algorithm::Sha512 => write!(f, "SHA512"),
+        }
+    }
+}
+
+/// Time based (RFC 6238) or counter based (RFC 4226) codes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Kind {
+    /// a new code every period seconds
+    Totp { period: u64 },
+    /// counter is the value for the next code, it goes up by one each time
+    Hotp { counter: u64 },
+}
+
+/// Codes can have this many digits at most, the HOTP truncation yields 31 bits.
+pub const MAX_DIGITS: u32 = 10;
+
+/// RFC 4226 HOTP code for counter, zero padded to digits.
+pub fn hotp(
+    secret: &[u8],
+    counter: u64,
+    digits: u32,
+    algorithm: Algorithm,
+) -> Result<String, Error> {
+    if !(1..=MAX_DIGITS).contains(&digits) {
+        return Err(Error::InvalidOtp);
+    }
+    let key = hmac::Key::new(algorithm.hmac(), secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let hash = tag.as_ref();
+    // dynamic truncation: the low nibble of the last byte picks 4 bytes, minus their top bit
+    let offset = usize::from(hash[hash.len() - 1] & 0xf);
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+    let code = u64::from(binary) % 10_u64.pow(digits);
+    Ok(format!("{code:0width$}", width = digits as usize))
+}
+
+/// RFC 6238 TOTP code for a unix time in seconds, counting periods from the epoch.
+pub fn totp(
+    secret: &[u8],
+    time: u64,
+    period: u64,
+    digits: u32,
+    algorithm: Algorithm,
+) -> Result<String, Error> {
+    if period == 0 {
+        return Err(Error::InvalidOtp);
+    }
+    hotp(secret, time / period, digits, algorithm)
+}
+
+/// A one-time code along with how long it lasts.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Code {
+    pub code: String,
+    /// TOTP: seconds until the next code
+    pub remaining: Option<u64>,
+    /// HOTP: the counter value the code was made from
+    pub counter: Option<u64>,
+}
+
+/// A code generator as described by an otpauth:// URI, e.g.
+/// otpauth://totp/Example:alice@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Otp {
+    pub kind: Kind,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    /// e.g. "Example:alice@example.com", percent decoded
+    pub label: String,
+    pub issuer: Option<String>,
+    secret: Secret,
+    /// parameters this module doesn't use, kept as is so to_uri doesn't drop them
+    extra: Vec<(String, String)>,
+}
+
+impl Otp {
+    /// Whether data looks like an otpauth:// URI, parse may still reject it.
+    pub fn is_uri(data: &[u8]) -> bool {
+        data.len() >= 10 && data[..10].eq_ignore_ascii_case(b"otpauth://")
+    }
+
+    /// Parses an otpauth:// URI in the Key Uri Format used by authenticator apps.
+    /// secret is required, hotp also needs counter. algorithm, digits and period default to
+    /// SHA1, 6 and 30.
+    pub fn parse(uri: &[u8]) -> Result<Otp, Error> {
+        if !Otp::is_uri(uri) {
+            return Err(Error::InvalidOtp);
+        }
+        let rest = &uri[10..];
+        let (path, query) = match rest.iter().position(|&b| b == b'?') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, &b""[..]),
+        };
+        let (kind, label) = match path.iter().position(|&b| b == b'/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => (path, &b""[..]),
+        };
+        let is_totp = if kind.eq_ignore_ascii_case(b"totp") {
+            true
+        } else if kind.eq_ignore_ascii_case(b"hotp") {
+            false
+        } else {
+            return Err(Error::InvalidOtp);
+        };
+
+        let mut otp = Otp {
+            kind: Kind::Totp { period: 30 },
+            algorithm: Algorithm::Sha1,
+            digits: 6,
+            label: String::from_utf8(percent_decode(label)?.to_vec())
+                .map_err(|_| Error::InvalidOtp)?,
+            issuer: None,
+            secret: Secret::zero(0),
+            extra: Vec::new(),
+        };
+        let mut secret = None;
+        let mut counter = None;
+        let mut period = 30;
+        for parameter in query.split(|&b| b == b'&').filter(|p| !p.is_empty()) {
+            let (key, value) = match parameter.iter().position(|&b| b == b'=') {
+                Some(i) => (&parameter[..i], &parameter[i + 1..]),
+                None => (parameter, &b""[..]),
+            };
+            let value = percent_decode(value)?;
+            let text = || str::from_utf8(&value).map_err(|_| Error::InvalidOtp);
+            let number = || text()?.parse::<u64>().map_err(|_| Error::InvalidOtp);
+            match key {
+                b"secret" => secret = Some(base32_decode(&value)?),
+                b"issuer" => otp.issuer = Some(text()?.into()),
+                b"algorithm" => {
+                    otp.algorithm = match text()?.to_ascii_uppercase().as_str() {
+                        "SHA1" => Algorithm::Sha1,
+                        "SHA256" => Algorithm::Sha256,
+                        "SHA512" => Algorithm::Sha512,
+                        _ => return Err(Error::InvalidOtp),
+                    }
+                }
+                b"digits" => {
+                    otp.digits = u32::try_from(number()?).map_err(|_| Error::InvalidOtp)?
+                }
+                b"period" => period = number()?,
+                b"counter" => counter = Some(number()?),
+                _ => {
+                    let key = String::from_utf8(key.to_vec()).map_err(|_| Error::InvalidOtp)?;
+                    otp.extra.push((key, text()?.into()));
+                }
+            }
+        }
+        otp.secret = secret.ok_or(Error::InvalidOtp)?;
+        otp.kind = match (is_totp, counter) {
+            (true, _) if period > 0 => Kind::Totp { period },
+            (false, Some(counter)) => Kind::Hotp { counter },
+            _ => return Err(Error::InvalidOtp),
+        };
+        if otp.secret.is_empty() || !(1..=MAX_DIGITS).contains(&otp.digits) {
+            return Err(Error::InvalidOtp);
+        }
+        Ok(otp)
+    }
+
+    /// Formats the generator back into an otpauth:// URI that parse accepts.
+    pub fn to_uri(&self) -> Secret {
+        let mut uri = Zeroizing::new(String::from("otpauth://"));
+        uri.push_str(match self.kind {
+            Kind::Totp { .. } => "totp/",
+            Kind::Hotp { .. } => "hotp/",
+        });
+        percent_encode(&self.label, &mut uri);
+        uri.push_str("?secret=");
+        base32_encode(self.secret.expose(), &mut uri);
+        if let Some(issuer) = &self.issuer {
+            uri.push_str("&issuer=");
+            percent_encode(issuer, &mut uri);
+        }
+        uri.push_str(&format!(
+            "&algorithm={}&digits={}",
+            self.algorithm, self.digits
+        ));
+        match self.kind {
+            Kind::Totp { period } => uri.push_str(&format!("&period={period}")),
+            Kind::Hotp { counter } => uri.push_str(&format!("&counter={counter}")),
+        }
+        for (key, value) in &self.extra {
+            uri.push('&');
+            percent_encode(key, &mut uri);
+            uri.push('=');
+            percent_encode(value, &mut uri);
+        }
+        Secret::new(uri.as_bytes().into())
+    }
+
+    /// The code for a unix time in seconds. HOTP ignores the time, uses the counter and then
+    /// advances it, so the Otp needs to be stored again afterwards.
+    pub fn generate(&mut self, time: u64) -> Result<Code, Error> {
+        let secret = self.secret.expose();
+        match &mut self.kind {
+            Kind::Totp { period } => Ok(Code {
+                code: totp(secret, time, *period, self.digits, self.algorithm)?,
+                remaining: Some(*period - time % *period),
+                counter: None,
+            }),
+            Kind::Hotp { counter } => {
+                let code = hotp(secret, *counter, self.digits, self.algorithm)?;
+                let used = *counter;
+                *counter = counter.checked_add(1).ok_or(Error::InvalidOtp)?;
+                Ok(Code {
+                    code,
+                    remaining: None,
+                    counter: Some(used),
+                })
+            }
+        }
+    }
+}
+
+/// Generates a code from the OTP field with prompt in the named form, or from the form's first
+/// field holding an otpauth:// URI if prompt is None. For HOTP the advanced counter is sealed
+/// back into the vault, which then has to be saved for the counter to stick. Counters don't go
+/// into the form's history.
+pub fn next_code(
+    vault: &mut Vault,
+    key: &[u8],
+    name: &[u8],
+    prompt: Option<&[u8]>,
+    time: u64,
+) -> Result<Code, Error> {
+    let mut form = vault.get_and_open(name, key)?;
+    let field: &mut Field = match prompt {
+        Some(prompt) => form
+            .iter_mut()
+            .find(|field| field.prompt.expose() == prompt)
+            .ok_or(Error::FieldNotFound)?,
+        None => form
+            .iter_mut()
+            .find(|field| Otp::is_uri(field.answer.expose()))
+            .ok_or(Error::FieldNotFound)?,
+    };
+    let mut otp = Otp::parse(field.answer.expose())?;
+    let code = otp.generate(time)?;
+    if code.counter.is_some() {
+        field.answer = otp.to_uri();
+        vault.insert(name, seal_form(name, &form, key)?);
+    }
+    Ok(code)
+}
+
+/// Decodes RFC 4648 base32, ignoring case, padding and spaces (secrets are often grouped).
+fn base32_decode(data: &[u8]) -> Result<Secret, Error> {
+    let mut decoded = Zeroizing::new(Vec::with_capacity(data.len() * 5 / 8));
+    let mut buffer = 0_u64;
+    let mut bits = 0;
+    for &b in data {
+        let value = match b.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            b'=' | b' ' => continue,
+            _ => return Err(Error::InvalidOtp),
+        };
+        buffer = (buffer << 5) | u64::from(value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    buffer.zeroize();
+    Ok(Secret::new(decoded.as_slice().into()))
+}
+
+/// Encodes as RFC 4648 base32 without padding, authenticator apps don't need it.
+fn base32_encode(data: &[u8], out: &mut String) {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut buffer = 0_u64;
+    let mut bits = 0;
+    for &b in data {
+        buffer = (buffer << 8) | u64::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    buffer.zeroize();
+}
+
+fn percent_decode(data: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error> {
+    let mut decoded = Zeroizing::new(Vec::with_capacity(data.len()));
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'%' => {
+                let hex = data.get(i + 1..i + 3).ok_or(Error::InvalidOtp)?;
+                let hex = str::from_utf8(hex).map_err(|_| Error::InvalidOtp)?;
+                decoded.push(u8::from_str_radix(hex, 16).map_err(|_| Error::InvalidOtp)?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(decoded)
+}
+
+/// Percent encodes everything but unreserved characters, and the ":" and "@" labels are made of.
+fn percent_encode(text: &str, out: &mut String) {
+    for &b in text.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~:@".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{field, test_header};
+
+    #[test]
+    /// RFC 4226 appendix D
+    fn hotp_test_vectors() {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.into_iter().enumerate() {
+            let computed = hotp(b"12345678901234567890", counter as u64, 6, Algorithm::Sha1);
+            assert_eq!(computed.unwrap(), code);
+        }
+        assert_eq!(
+            hotp(b"12345678901234567890", 0, 0, Algorithm::Sha1),
+            Err(Error::InvalidOtp)
+        );
+    }
+
+    #[test]
+    /// RFC 6238 appendix B
+    fn totp_test_vectors() {
+        let seeds: [(&[u8], Algorithm); 3] = [
+            (b"12345678901234567890", Algorithm::Sha1),
+            (b"12345678901234567890123456789012", Algorithm::Sha256),
+            (
+                b"1234567890123456789012345678901234567890123456789012345678901234",
+                Algorithm::Sha512,
+            ),
+        ];
+        let expected: [(u64, [&str; 3]); 6] = [
+            (59, ["94287082", "46119246", "90693936"]),
+            (1111111109, ["07081804", "68084774", "25091201"]),
+            (1111111111, ["14050471", "67062674", "99943326"]),
+            (1234567890, ["89005924", "91819424", "93441116"]),
+            (2000000000, ["69279037", "90698825", "38618901"]),
+            (20000000000, ["65353130", "77737706", "47863826"]),
+        ];
+        for (time, codes) in expected {
+            for ((seed, algorithm), code) in seeds.iter().zip(codes) {
+                assert_eq!(totp(seed, time, 30, 8, *algorithm).unwrap(), code);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_uri() {
+        let mut otp = Otp::parse(
+            b"otpauth://totp/ACME%20Co:john@example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+              &issuer=ACME%20Co&algorithm=SHA1&digits=8&period=30&image=x",
+        )
+        .unwrap();
+        assert_eq!(otp.label, "ACME Co:john@example.com");
+        assert_eq!(otp.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(otp.kind, Kind::Totp { period: 30 });
+        assert_eq!(otp.secret.expose(), b"12345678901234567890");
+        let code = otp.generate(59).unwrap();
+        assert_eq!(code.code, "94287082");
+        assert_eq!(code.remaining, Some(1));
+        assert_eq!(Otp::parse(otp.to_uri().expose()).unwrap(), otp);
+
+        // lowercase secret with padding, defaults for everything else
+        let otp = Otp::parse(b"otpauth://hotp/x?secret=gezdgnbvgy3tqojq====&counter=3").unwrap();
+        assert_eq!(otp.algorithm, Algorithm::Sha1);
+        assert_eq!(otp.digits, 6);
+        assert_eq!(otp.secret.expose(), b"1234567890");
+
+        for invalid in [
+            &b"https://totp/x?secret=GEZA"[..],
+            b"otpauth://totp/x",
+            b"otpauth://totp/x?secret=GEZ1",
+            b"otpauth://hotp/x?secret=GEZA",
+            b"otpauth://totp/x?secret=GEZA&period=0",
+            b"otpauth://totp/x?secret=GEZA&digits=11",
+            b"otpauth://totp/x?secret=GEZA&algorithm=MD5",
+            b"otpauth://totp/x%2?secret=GEZA",
+        ] {
+            assert_eq!(Otp::parse(invalid), Err(Error::InvalidOtp));
+        }
+    }
+
+    #[test]
+    fn hotp_counter_written_back() {
+        let (header, key) = test_header();
+        let mut vault = Vault::new(header);
+        let form = [
+            field(b"password", b"hunter2"),
+            field(
+                b"2fa",
+                b"otpauth://hotp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1",
+            ),
+        ];
+        vault
+            .seal_and_insert(b"bank", &form.into(), key.expose())
+            .unwrap();
+
+        let code = next_code(&mut vault, key.expose(), b"bank", None, 0).unwrap();
+        assert_eq!((code.code.as_str(), code.counter), ("287082", Some(1)));
+        let code = next_code(&mut vault, key.expose(), b"bank", Some(b"2fa"), 0).unwrap();
+        assert_eq!((code.code.as_str(), code.counter), ("359152", Some(2)));
+        assert_eq!(vault.history(b"bank", key.expose()), Ok(Vec::new()));
+        assert_eq!(
+            next_code(&mut vault, key.expose(), b"bank", Some(b"password"), 0),
+            Err(Error::InvalidOtp)
+        );
+        assert_eq!(
+            next_code(&mut vault, key.expose(), b"bank", Some(b"pin"), 0),
+            Err(Error::FieldNotFound)
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::secret::Secret;
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+/// Longest password the prompts accept, in bytes.
+pub const MAX_PASSWORD_LEN: usize = 1024;
+
+/// Prints message to stderr, so it stays out of redirected output, and reads a password from
+/// stdin, without the trailing newline.
+/// When stdin is a terminal, echo is turned off while the password is typed so it doesn't end
+/// up on screen or in the scrollback. Otherwise (pipes, tests) the line is read as is.
+pub fn password(message: &str) -> io::Result<Secret> {
+    eprint!("{message}");
+    io::stderr().flush()?;
+    let _echo_off = EchoOff::new();
+    read_password(&mut io::stdin().lock())
+}
+
+/// Confirmation mode for choosing a new password: prompts with message, then again with
+/// confirm_message, and fails unless both passwords are the same.
+pub fn confirmed_password(message: &str, confirm_message: &str) -> io::Result<Secret> {
+    let password = password(message)?;
+    if self::password(confirm_message)? != password {
+        return Err(io::Error::other("passwords don't match"));
+    }
+    Ok(password)
+}
+
+/// Reads one line from input into a Secret, byte by byte so nothing past the newline is consumed.
+/// A trailing "\r" is dropped along with the newline. Fails on EOF before anything was read, and
+/// on lines longer than MAX_PASSWORD_LEN (which are consumed up to the newline regardless).
+fn read_password(input: &mut impl Read) -> io::Result<Secret> {
+    let mut buffer = Secret::zero(MAX_PASSWORD_LEN + 1);
+    let mut len = 0;
+    let mut byte = Zeroizing::new([0_u8]);
+    let mut eof = true;
+    while input.read(&mut *byte)? == 1 {
+        eof = false;
+        if byte[0] == b'\n' {
+            break;
+        }
+        // keep counting past the end so overlong lines can be rejected
+        if len < buffer.len() {
+            buffer[len] = byte[0];
+        }
+        len += 1;
+    }
+    if eof {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if len > MAX_PASSWORD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "password is too long",
+        ));
+    }
+    if len > 0 && buffer[len - 1] == b'\r' {
+        len -= 1;
+    }
+    Ok(Secret::new(Box::from(&buffer[..len])))
+}
+
+/// Turns terminal echo off for as long as it's alive, if stdin is a terminal.
+/// Echo is also turned back on if SIGINT, SIGTERM or SIGHUP end the process meanwhile.
+#[cfg(unix)]
+struct EchoOff {
+    original: libc::termios,
+    // handlers replaced by restore_echo, indexed like SIGNALS
+    previous: [libc::sigaction; 3],
+}
+
+// Signals that would end the process with echo still off.
+#[cfg(unix)]
+const SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+// Copy of EchoOff::original for restore_echo, which can't get at the EchoOff. Only written
+// while restore_echo isn't installed.
+#[cfg(unix)]
+static mut ORIGINAL: std::mem::MaybeUninit<libc::termios> = std::mem::MaybeUninit::uninit();
+
+/// Signal handler: turns echo back on, then lets the signal end the process as it would have.
+#[cfg(unix)]
+extern "C" fn restore_echo(signal: libc::c_int) {
+    // SAFETY: ORIGINAL was initialized before this handler was installed, and tcsetattr, signal
+    // and raise are async-signal-safe.
+    unsafe {
+        libc::tcsetattr(
+            libc::STDIN_FILENO,
+            libc::TCSANOW,
+            (&raw const ORIGINAL).cast::<libc::termios>(),
+        );
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+#[cfg(unix)]
+impl EchoOff {
+    fn new() -> Option<Self> {
+        // SAFETY: termios is a plain C struct that tcgetattr fully initializes, stdin's file
+        // descriptor stays open for the whole process.
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return None;
+            }
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return None;
+            }
+            let original = termios;
+            // still echo the newline, so whatever is printed next starts on its own line
+            termios.c_lflag &= !libc::ECHO;
+            termios.c_lflag |= libc::ECHONL;
+            (&raw mut ORIGINAL).write(std::mem::MaybeUninit::new(original));
+            let mut handler: libc::sigaction = std::mem::zeroed();
+            handler.sa_sigaction = restore_echo as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut handler.sa_mask);
+            let mut previous: [libc::sigaction; 3] = std::mem::zeroed();
+            for (signal, previous) in SIGNALS.iter().zip(&mut previous) {
+                libc::sigaction(*signal, &handler, previous);
+            }
+            let echo_off = EchoOff { original, previous };
+            // dropping echo_off restores the handlers
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &termios) != 0 {
+                return None;
+            }
+            Some(echo_off)
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        // SAFETY: restores the attributes tcgetattr returned in new, then the handlers sigaction
+        // returned there.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original);
+            for (signal, previous) in SIGNALS.iter().zip(&self.previous) {
+                libc::sigaction(*signal, previous, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// Without termios, passwords are read like any other line.
+#[cfg(not(unix))]
+struct EchoOff;
+
+#[cfg(not(unix))]
+impl EchoOff {
+    fn new() -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_lines() {
+        let mut input: &[u8] = b"hunter2\n\n*******\r\nlast line";
+        assert_eq!(read_password(&mut input).unwrap().expose(), b"hunter2");
+        assert!(read_password(&mut input).unwrap().is_empty());
+        assert_eq!(read_password(&mut input).unwrap().expose(), b"*******");
+        assert_eq!(read_password(&mut input).unwrap().expose(), b"last line");
+        let eof = read_password(&mut input).unwrap_err();
+        assert_eq!(eof.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reject_long_password() {
+        let longest = vec![b'a'; MAX_PASSWORD_LEN];
+        let too_long = vec![b'a'; MAX_PASSWORD_LEN + 1];
+        let input = [&longest[..], b"\n", &too_long, b"\n", b"next\n"].concat();
+        let mut input = &input[..];
+        assert_eq!(read_password(&mut input).unwrap().len(), MAX_PASSWORD_LEN);
+        let error = read_password(&mut input).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // the rest of the long line was skipped
+        assert_eq!(read_password(&mut input).unwrap().expose(), b"next");
+    }
+}
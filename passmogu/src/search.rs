@@ -0,0 +1,233 @@
+use crate::error::Error;
+use crate::vault::{FieldKind, Vault};
+
+/// The part of a form a query matched. Each form is listed once, for the part that scored best.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Matched {
+    Name,
+    Tag,
+    Folder,
+    Url,
+    Prompt,
+}
+
+impl Matched {
+    /// Subtracted from the score of a match in this part, so that a name beats an equally good
+    /// tag, and a tag an equally good prompt.
+    fn penalty(self) -> u32 {
+        match self {
+            Matched::Name => 0,
+            Matched::Tag | Matched::Folder => 30,
+            Matched::Url => 40,
+            Matched::Prompt => 60,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Matched::Name => "name",
+            Matched::Tag => "tag",
+            Matched::Folder => "folder",
+            Matched::Url => "url",
+            Matched::Prompt => "prompt",
+        }
+    }
+}
+
+/// A form found by search.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Hit<'a> {
+    pub name: &'a [u8],
+    pub matched: Matched,
+    /// higher is better, see score
+    pub score: u32,
+}
+
+/// How well query matches text, None if it doesn't. ASCII case is ignored.
+/// From best to worst: all of text, a prefix, a substring starting a word, any substring, and
+/// query's bytes appearing in order with others in between (fuzzy). Shorter texts and earlier
+/// or tighter matches score higher within each of those. An empty query matches anything.
+pub fn score(text: &[u8], query: &[u8]) -> Option<u32> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    if text.len() < query.len() {
+        return None;
+    }
+    let capped = |n: usize, cap: u32| u32::try_from(n).unwrap_or(cap).min(cap);
+    if text.eq_ignore_ascii_case(query) {
+        return Some(1000);
+    }
+    if text[..query.len()].eq_ignore_ascii_case(query) {
+        return Some(900 - capped(text.len() - query.len(), 99));
+    }
+    let mut substring = None;
+    for (i, window) in text.windows(query.len()).enumerate() {
+        if window.eq_ignore_ascii_case(query) {
+            if !text[i - 1].is_ascii_alphanumeric() {
+                return Some(700 - capped(i, 99));
+            }
+            substring.get_or_insert(500 - capped(i, 99));
+        }
+    }
+    if substring.is_some() {
+        return substring;
+    }
+    // the tightest span holding query's bytes in order, trying each place the first one appears
+    let mut tightest = None;
+    for start in 0..text.len() {
+        let mut bytes = query.iter();
+        let mut wanted = bytes.next();
+        for (i, byte) in text.iter().enumerate().skip(start) {
+            match wanted {
+                Some(want) if byte.eq_ignore_ascii_case(want) => wanted = bytes.next(),
+                Some(_) if i == start => break,
+                Some(_) => continue,
+                None => break,
+            }
+            if wanted.is_none() {
+                let span = i + 1 - start;
+                tightest = Some(tightest.map_or(span, |tightest: usize| tightest.min(span)));
+                break;
+            }
+        }
+    }
+    tightest.map(|span| 300 - capped(span - query.len(), 199))
+}
+
+/// The candidates query matches, best first, ties in byte order.
+pub fn rank<'a>(candidates: impl IntoIterator<Item = &'a [u8]>, query: &[u8]) -> Vec<&'a [u8]> {
+    let mut ranked: Vec<_> = candidates
+        .into_iter()
+        .filter_map(|candidate| Some((score(candidate, query)?, candidate)))
+        .collect();
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    ranked.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// Forms whose name, tags, folder, field prompts or URLs match query, best first.
+/// Prompts and answers are decrypted with key to be searched, only in memory, so this works no
+/// matter what the vault file keeps encrypted. Answers are only searched in fields that hold a
+/// URL, either by their kind or because they start with http:// or https://.
+pub fn search<'a>(vault: &'a Vault, key: &[u8], query: &[u8]) -> Result<Vec<Hit<'a>>, Error> {
+    let mut hits = Vec::new();
+    for name in vault.form_names() {
+        let mut best: Option<Hit> = None;
+        let mut consider = |text: &[u8], matched: Matched| {
+            let Some(score) = score(text, query) else {
+                return;
+            };
+            let score = score.saturating_sub(matched.penalty());
+            if best.is_none_or(|best| score > best.score) {
+                best = Some(Hit {
+                    name,
+                    matched,
+                    score,
+                });
+            }
+        };
+        consider(name, Matched::Name);
+        if let Some(meta) = vault.meta(name) {
+            for tag in &meta.tags {
+                consider(tag, Matched::Tag);
+            }
+            if let Some(folder) = &meta.folder {
+                consider(folder, Matched::Folder);
+            }
+        }
+        for field in vault.get_and_open(name, key)? {
+            consider(field.prompt.expose(), Matched::Prompt);
+            let answer = field.answer.expose();
+            let is_url = field.kind == Some(FieldKind::Url)
+                || answer.starts_with(b"http://")
+                || answer.starts_with(b"https://");
+            if is_url {
+                consider(answer, Matched::Url);
+            }
+        }
+        hits.extend(best);
+    }
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then(a.name.cmp(b.name)));
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{field, test_header};
+
+    #[test]
+    fn score_matches() {
+        let order = [
+            score(b"github", b"github"),
+            score(b"GitHub", b"git"),
+            score(b"GitHub Enterprise", b"git"),
+            score(b"work github", b"git"),
+            score(b"digital", b"git"),
+            score(b"gitlab", b"gtl"),
+            score(b"g_i_t", b"git"),
+        ];
+        assert!(order.iter().all(Option::is_some));
+        assert!(order.windows(2).all(|pair| pair[0] > pair[1]), "{order:?}");
+        assert_eq!(score(b"gitlab", b""), Some(0));
+        assert_eq!(score(b"gitlab", b"tig"), None);
+        assert_eq!(score(b"git", b"gitlab"), None);
+        // the tightest span counts, not the first one
+        assert_eq!(score(b"a--b a-b", b"ab"), score(b"a-b", b"ab"));
+        assert!(score(b"a-b", b"ab") > score(b"a--b", b"ab"));
+    }
+
+    #[test]
+    fn rank_candidates() {
+        let candidates: [&[u8]; 5] = [b"remove", b"rename", b"rm", b"ls", b"restore"];
+        assert_eq!(rank(candidates, b"rm"), [&b"rm"[..], b"remove", b"rename"]);
+        assert_eq!(rank(candidates, b"re").len(), 3);
+        assert_eq!(rank(candidates, b"").len(), 5);
+    }
+
+    #[test]
+    fn search_forms() {
+        let (header, key) = test_header();
+        let key = key.expose();
+        let mut vault = Vault::new(header);
+        vault
+            .seal_and_insert(b"GitHub", &[field(b"password", b"hunter2")].into(), key)
+            .unwrap();
+        vault
+            .seal_and_insert(
+                b"work",
+                &[field(b"website", b"https://git.example.com")].into(),
+                key,
+            )
+            .unwrap();
+        vault
+            .seal_and_insert(b"bank", &[field(b"PIN", b"1234")].into(), key)
+            .unwrap();
+        vault.meta_mut(b"bank").unwrap().add_tag(b"money");
+        vault.meta_mut(b"work").unwrap().folder = Some(Box::from(&b"jobs"[..]));
+
+        let hits = search(&vault, key, b"git").unwrap();
+        let found: Vec<_> = hits.iter().map(|hit| (hit.name, hit.matched)).collect();
+        assert_eq!(
+            found,
+            [(&b"GitHub"[..], Matched::Name), (b"work", Matched::Url)]
+        );
+        let hits = search(&vault, key, b"pin").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            (hits[0].name, hits[0].matched),
+            (&b"bank"[..], Matched::Prompt)
+        );
+        assert_eq!(
+            search(&vault, key, b"money").unwrap()[0].matched,
+            Matched::Tag
+        );
+        assert_eq!(
+            search(&vault, key, b"jobs").unwrap()[0].matched,
+            Matched::Folder
+        );
+        // answers that aren't URLs aren't searched
+        assert!(search(&vault, key, b"hunter2").unwrap().is_empty());
+        assert_eq!(search(&vault, key, b"").unwrap().len(), 3);
+    }
+}
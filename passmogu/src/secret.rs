@@ -72,7 +72,7 @@ mod tests {
     fn secret_is_zero() {
         let mut buffer = Secret::zero(32);
 
-        for (i, byte) in b"this is my password".into_iter().enumerate() {
+        for (i, byte) in b"this is my password".iter().enumerate() {
             buffer[i] = *byte;
         }
         let zero = Secret::zero(32);
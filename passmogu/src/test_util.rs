@@ -0,0 +1,24 @@
+use crate::encrypt::KdfParams;
+use crate::header::Header;
+use crate::secret::Secret;
+use crate::vault::Field;
+use std::num::NonZeroU32;
+
+// tests don't need to be slow to brute force
+pub const FAST_KDF: KdfParams = KdfParams::Pbkdf2HmacSha256 {
+    iterations: NonZeroU32::new(1).unwrap(),
+};
+
+/// Header for the master password hunter2, and its data key.
+pub fn test_header() -> (Header, Secret) {
+    Header::new(FAST_KDF, b"hunter2").unwrap()
+}
+
+/// Field of no particular kind.
+pub fn field(prompt: &[u8], answer: &[u8]) -> Field {
+    Field {
+        prompt: Secret::new(prompt.into()),
+        answer: Secret::new(answer.into()),
+        kind: None,
+    }
+}
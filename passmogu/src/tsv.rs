@@ -0,0 +1,113 @@
+use crate::encrypt::Ciphertext;
+use crate::error::Error;
+use crate::hex::Hex;
+use crate::secret::Secret;
+use crate::vault::FieldKind;
+
+/// A tsv token along with where it was found, so parse errors can point at it.
+#[derive(Clone, Copy)]
+pub(crate) struct Token<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+impl<'a> Token<'a> {
+    pub(crate) fn decode_hex(&self) -> Result<Secret, Error> {
+        match Hex::new(self.bytes) {
+            Ok(hex) => Ok(hex.decode()),
+            Err(offset) => Err(Error::MalformedHex {
+                line: self.line,
+                column: self.column + offset,
+            }),
+        }
+    }
+
+    /// Splits the token at the first separator into the bytes before it and the token after it.
+    pub(crate) fn split_once(self, separator: u8) -> Option<(&'a [u8], Self)> {
+        let i = self.bytes.iter().position(|byte| *byte == separator)?;
+        let rest = Token {
+            bytes: &self.bytes[i + 1..],
+            column: self.column + i + 1,
+            ..self
+        };
+        Some((&self.bytes[..i], rest))
+    }
+
+    /// Splits the "kind:" prefix off a prompt token, see Vault.
+    /// Fails with UnknownFieldKind if there's a prefix that doesn't name a FieldKind.
+    pub(crate) fn split_kind(self) -> Result<(Option<FieldKind>, Self), Error> {
+        let Some((kind, rest)) = self.split_once(b':') else {
+            return Ok((None, self));
+        };
+        let kind = FieldKind::from_name(kind).ok_or(Error::UnknownFieldKind {
+            line: self.line,
+            column: self.column,
+        })?;
+        Ok((Some(kind), rest))
+    }
+
+    pub(crate) fn decode_ciphertext(&self) -> Result<Ciphertext, Error> {
+        Ciphertext::new(self.decode_hex()?).ok_or(Error::TruncatedCiphertext {
+            line: self.line,
+            column: self.column,
+        })
+    }
+}
+
+/// Splits one line of tsv into Tokens.
+pub(crate) struct Tokens<'a> {
+    rest: Option<&'a [u8]>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub(crate) fn new(line: usize, bytes: &'a [u8]) -> Self {
+        Tokens {
+            rest: Some(bytes),
+            line,
+            column: 1,
+        }
+    }
+
+    /// Returns the next token, failing with TruncatedRow when the line has ended.
+    pub(crate) fn require(&mut self) -> Result<Token<'a>, Error> {
+        self.next().ok_or(Error::TruncatedRow { line: self.line })
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest?;
+        let token = Token {
+            bytes: rest,
+            line: self.line,
+            column: self.column,
+        };
+        match rest.iter().position(|byte| *byte == b'\t') {
+            Some(i) => {
+                self.rest = Some(&rest[i + 1..]);
+                self.column += i + 1;
+                Some(Token {
+                    bytes: &rest[..i],
+                    ..token
+                })
+            }
+            None => {
+                self.rest = None;
+                self.column += rest.len() + 1;
+                Some(token)
+            }
+        }
+    }
+}
+
+/// Splits data into (line number, line) pairs, without the '\n'.
+pub(crate) fn lines(data: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    data.split(|byte| *byte == b'\n')
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+}
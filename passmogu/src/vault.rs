@@ -1,7 +1,14 @@
+use crate::encrypt::{self, Ciphertext, KdfParams};
+use crate::error::Error;
+use crate::header::Header;
 use crate::hex::Hex;
 use crate::secret::Secret;
+use crate::tsv::{self, Token, Tokens};
 use std::collections::HashMap;
+use std::fmt;
 use std::ops::Index;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
 
 /// A field is a pair of prompt and answer e.g. ("password", "hunter2")
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -10,105 +17,973 @@ pub struct Field {
     pub prompt: Secret,
     /// value to populate into field
     pub answer: Secret,
+    /// what the answer is, None for fields that were stored without one
+    pub kind: Option<FieldKind>,
+}
+
+/// What a field's answer is, so frontends don't have to guess from the prompt.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FieldKind {
+    /// username or other account name
+    Login,
+    Password,
+    Url,
+    Email,
+    /// free form text
+    Note,
+    /// otpauth:// URI, see the otp module
+    Otp,
+    /// payment card number
+    Card,
+    /// anything else that should be kept out of sight
+    Hidden,
+    /// anything else that's fine to display
+    Visible,
+}
+
+impl FieldKind {
+    pub const ALL: [FieldKind; 9] = [
+        FieldKind::Login,
+        FieldKind::Password,
+        FieldKind::Url,
+        FieldKind::Email,
+        FieldKind::Note,
+        FieldKind::Otp,
+        FieldKind::Card,
+        FieldKind::Hidden,
+        FieldKind::Visible,
+    ];
+
+    /// Lowercase name, as serialized.
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldKind::Login => "login",
+            FieldKind::Password => "password",
+            FieldKind::Url => "url",
+            FieldKind::Email => "email",
+            FieldKind::Note => "note",
+            FieldKind::Otp => "otp",
+            FieldKind::Card => "card",
+            FieldKind::Hidden => "hidden",
+            FieldKind::Visible => "visible",
+        }
+    }
+
+    /// Inverse of name.
+    pub fn from_name(name: &[u8]) -> Option<FieldKind> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name().as_bytes() == name)
+    }
+
+    /// Whether frontends should mask answers of this kind unless asked to reveal them.
+    pub fn is_hidden(self) -> bool {
+        matches!(
+            self,
+            FieldKind::Password | FieldKind::Otp | FieldKind::Card | FieldKind::Hidden
+        )
+    }
+
+    /// Best guess at the kind of a field from its prompt and answer, for fields entered without one.
+    /// e.g. "Username" is a Login and an answer starting with "otpauth://" is an Otp.
+    pub fn guess(prompt: &[u8], answer: &[u8]) -> Option<FieldKind> {
+        if answer.starts_with(b"otpauth://") {
+            return Some(FieldKind::Otp);
+        }
+        let prompt = prompt.to_ascii_lowercase();
+        let has = |word: &str| prompt.windows(word.len()).any(|w| w == word.as_bytes());
+        // earlier entries win, e.g. "email password" is a Password
+        let guesses = [
+            (
+                &["password", "passphrase", "passcode"][..],
+                FieldKind::Password,
+            ),
+            (&["2fa", "otp", "totp", "one-time"], FieldKind::Otp),
+            (&["card"], FieldKind::Card),
+            (&["email", "e-mail"], FieldKind::Email),
+            (&["user", "login", "account"], FieldKind::Login),
+            (&["url", "website", "site"], FieldKind::Url),
+            (&["note"], FieldKind::Note),
+        ];
+        guesses
+            .into_iter()
+            .find(|(words, _)| words.iter().any(|word| has(word)))
+            .map(|(_, kind)| kind)
+    }
+}
+
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 /// A form is nothing more than a collection of fields to populate.
 /// Any list of fields consitutes a valid form, so we simply define a type alias here.
 pub type Form = Box<[Field]>;
 
+/// A Field whose prompt and answer have been encrypted by seal_form.
+/// Vault only stores sealed fields, so a plaintext Form can't be persisted by mistake.
+/// The kind stays in plaintext, but the answer's ciphertext is bound to it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SealedField {
+    pub prompt: Ciphertext,
+    pub answer: Ciphertext,
+    pub kind: Option<FieldKind>,
+}
+
+/// Encrypted counterpart of Form, see seal_form and open_form.
+pub type SealedForm = Box<[SealedField]>;
+
+/// Which half of a Field a ciphertext holds, and whether the field is in a form or its history.
+#[derive(Clone, Copy)]
+enum Role {
+    Prompt,
+    Answer,
+    OldPrompt,
+    OldAnswer,
+}
+
+/// Associated data binding a field's ciphertext to its slot: form name, field index (or index into
+/// the form's history) and role.
+/// Moving a ciphertext to another form, position or role makes it fail to decrypt.
+/// The name is length prefixed so different (name, index) pairs can't produce the same bytes.
+/// An answer is also bound to the field's kind, fields without a kind are bound to an empty one.
+fn field_aad(name: &[u8], index: usize, role: Role, kind: Option<FieldKind>) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(name.len() + 17);
+    aad.extend((name.len() as u64).to_le_bytes());
+    aad.extend(name);
+    aad.extend((index as u64).to_le_bytes());
+    let kind = kind.map_or("", FieldKind::name).as_bytes();
+    match role {
+        Role::Prompt => aad.push(b'P'),
+        Role::OldPrompt => aad.push(b'p'),
+        Role::Answer => {
+            aad.push(b'A');
+            aad.extend(kind);
+        }
+        Role::OldAnswer => {
+            aad.push(b'a');
+            aad.extend(kind);
+        }
+    }
+    aad
+}
+
+/// Encrypts field as the index-th field of form name, or its index-th history entry if old.
+fn seal_field(
+    name: &[u8],
+    index: usize,
+    field: &Field,
+    key: &[u8],
+    old: bool,
+) -> Result<SealedField, Error> {
+    let (prompt_role, answer_role) = roles(old);
+    Ok(SealedField {
+        prompt: encrypt::encrypt_with_aad(
+            field.prompt.clone(),
+            key,
+            &field_aad(name, index, prompt_role, field.kind),
+        )?,
+        answer: encrypt::encrypt_with_aad(
+            field.answer.clone(),
+            key,
+            &field_aad(name, index, answer_role, field.kind),
+        )?,
+        kind: field.kind,
+    })
+}
+
+/// Inverse of seal_field. Fails with Error::Corrupt if either half doesn't decrypt.
+fn open_field(
+    name: &[u8],
+    index: usize,
+    field: &SealedField,
+    key: &[u8],
+    old: bool,
+) -> Result<Field, Error> {
+    let (prompt_role, answer_role) = roles(old);
+    let corrupt = |e| match e {
+        Error::Authentication => Error::Corrupt {
+            form: name.into(),
+            index,
+            history: old,
+        },
+        e => e,
+    };
+    Ok(Field {
+        prompt: encrypt::decrypt_with_aad(
+            field.prompt.clone(),
+            key,
+            &field_aad(name, index, prompt_role, field.kind),
+        )
+        .map_err(corrupt)?,
+        answer: encrypt::decrypt_with_aad(
+            field.answer.clone(),
+            key,
+            &field_aad(name, index, answer_role, field.kind),
+        )
+        .map_err(corrupt)?,
+        kind: field.kind,
+    })
+}
+
+fn roles(old: bool) -> (Role, Role) {
+    match old {
+        false => (Role::Prompt, Role::Answer),
+        true => (Role::OldPrompt, Role::OldAnswer),
+    }
+}
+
+/// Encrypts every prompt and answer of a plaintext form that will be stored under name.
+pub fn seal_form(name: &[u8], form: &Form, key: &[u8]) -> Result<SealedForm, Error> {
+    let mut sealed = Vec::with_capacity(form.len());
+    for (i, field) in form.iter().enumerate() {
+        sealed.push(seal_field(name, i, field, key, false)?);
+    }
+    Ok(sealed.into_boxed_slice())
+}
+
+/// Decrypts a form sealed by seal_form. Fails with Error::Corrupt if any ciphertext was tampered
+/// with or isn't in the slot (form name, field index, prompt or answer) it was sealed for. The key
+/// isn't checked separately, so a wrong one fails the same way.
+pub fn open_form(name: &[u8], form: &SealedForm, key: &[u8]) -> Result<Form, Error> {
+    let mut opened = Vec::with_capacity(form.len());
+    for (i, field) in form.iter().enumerate() {
+        opened.push(open_field(name, i, field, key, false)?);
+    }
+    Ok(opened.into_boxed_slice())
+}
+
+/// A previous version of one of a form's fields, see Vault::history.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HistoryEntry {
+    pub field: Field,
+    /// when the field's answer was replaced, or the field removed, in seconds since the Unix epoch
+    pub replaced: u64,
+}
+
+/// A HistoryEntry whose field has been encrypted like a form's fields are, but for its own slots.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct SealedEntry {
+    field: SealedField,
+    replaced: u64,
+}
+
+/// How many previous answers of each field a Vault keeps unless told otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 10;
+
+/// What a Vault knows about a form besides its fields. Timestamps are seconds since the Unix epoch.
+/// Rows loaded without metadata tokens have no created or modified time.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct FormMeta {
+    /// set by the first Vault::insert
+    pub created: Option<u64>,
+    /// set by every Vault::insert
+    pub modified: Option<u64>,
+    /// set by mark_used, frontends decide what counts as a use. passmogu-cli only marks get and
+    /// clip in a session, saved along with its next change. One-shot get and otp, and the agent,
+    /// leave it alone since saving just for that would push out a backup generation every time.
+    pub last_used: Option<u64>,
+    pub tags: Vec<Box<[u8]>>,
+    /// '/' separated path, e.g. "work/email"
+    pub folder: Option<Box<[u8]>>,
+    pub favorite: bool,
+}
+
+impl FormMeta {
+    pub fn mark_used(&mut self) {
+        self.last_used = Some(now());
+    }
+
+    pub fn has_tag(&self, tag: &[u8]) -> bool {
+        self.tags.iter().any(|t| **t == *tag)
+    }
+
+    /// Adds tag unless it's already there, returns whether it was added.
+    pub fn add_tag(&mut self, tag: &[u8]) -> bool {
+        let added = !self.has_tag(tag);
+        if added {
+            self.tags.push(tag.into());
+        }
+        added
+    }
+
+    /// Returns whether tag was there.
+    pub fn remove_tag(&mut self, tag: &[u8]) -> bool {
+        let len = self.tags.len();
+        self.tags.retain(|t| **t != *tag);
+        self.tags.len() != len
+    }
+
+    /// Whether the form is in folder or one of its subfolders, e.g. "work/email" is in "work".
+    /// A trailing '/' in folder is ignored.
+    pub fn in_folder(&self, folder: &[u8]) -> bool {
+        let folder = folder.strip_suffix(b"/").unwrap_or(folder);
+        self.folder.as_deref().is_some_and(|path| {
+            path.strip_prefix(folder)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(b"/"))
+        })
+    }
+
+    /// Appends the metadata tokens of a row, each preceded by '\t'. See Vault.
+    fn dump(&self, row: &mut Vec<u8>) {
+        let mut token = |key: &str, value: &[u8]| {
+            row.push(b'\t');
+            row.extend(key.as_bytes());
+            row.push(b'=');
+            row.extend(value);
+        };
+        let times = [
+            ("created", self.created),
+            ("modified", self.modified),
+            ("last_used", self.last_used),
+        ];
+        for (key, time) in times {
+            if let Some(time) = time {
+                token(key, time.to_string().as_bytes());
+            }
+        }
+        if let Some(folder) = &self.folder {
+            token("folder", Hex::encode(folder).as_slice());
+        }
+        for tag in &self.tags {
+            token("tag", Hex::encode(tag).as_slice());
+        }
+        if self.favorite {
+            token("favorite", b"1");
+        }
+    }
+
+    /// Exact length of what dump appends.
+    fn dump_len(&self) -> usize {
+        let mut row = Vec::new();
+        self.dump(&mut row);
+        row.len()
+    }
+
+    /// Parses one "key=value" token written by dump.
+    fn load_token(&mut self, token: Token) -> Result<(), Error> {
+        let malformed = |token: Token| Error::MalformedMetadata {
+            line: token.line,
+            column: token.column,
+        };
+        let (key, value) = token.split_once(b'=').ok_or(malformed(token))?;
+        let time = || {
+            str::from_utf8(value.bytes)
+                .ok()
+                .filter(|time| time.bytes().all(|byte| byte.is_ascii_digit()))
+                .and_then(|time| time.parse().ok())
+                .ok_or(malformed(value))
+        };
+        match key {
+            b"created" => self.created = Some(time()?),
+            b"modified" => self.modified = Some(time()?),
+            b"last_used" => self.last_used = Some(time()?),
+            b"folder" => self.folder = Some(value.decode_hex()?.expose().into()),
+            b"tag" => self.tags.push(value.decode_hex()?.expose().into()),
+            b"favorite" if value.bytes == b"1" => self.favorite = true,
+            _ => return Err(malformed(token)),
+        }
+        Ok(())
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 /// Vault maps form_name -> form and mostly mirrors a subset of HashMap's API.
-/// It's serializable to and from tsv. The format is "form_name\tprompt1\tanswer1\tprompt2\tanswer2\n".
-/// The empty Vault is "" (not "\n"). Because of the tsv format, strings are hex encoded to avoid '\t'.
-#[derive(Debug, PartialEq, Eq, Default)]
-pub struct Vault(HashMap<Box<[u8]>, Form>); // memory protections for form_name are not as strong.
+/// It's serializable to and from tsv. The first line is always the Header, followed by one row per
+/// form. The row format is "form_name\tprompt1\tanswer1\tprompt2\tanswer2\n".
+/// The empty Vault is just the header line. Because of the tsv format, strings are hex encoded to avoid '\t'.
+/// A field with a kind has its name and a ':' in front of the prompt, e.g. "password:prompt2".
+/// Hex is uppercase, so this can't be mistaken for part of the prompt.
+/// Each form's FormMeta goes between its name and its first field as "key=value" tokens, e.g.
+/// "form_name\tcreated=1700000000\ttag=776F726B\tprompt1\tanswer1\n". Folders and tags are hex
+/// encoded, timestamps are decimal and favorite is "favorite=1". Fields never contain a '='.
+/// A form's history goes in a row of its own, oldest entry first:
+/// "HISTORY\tform_name\treplaced1\tprompt1\tanswer1\treplaced2\tprompt2\tanswer2\n".
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Vault {
+    header: Header,
+    forms: HashMap<Box<[u8]>, SealedForm>, // memory protections for form_name are not as strong.
+    // has the same keys as forms
+    meta: HashMap<Box<[u8]>, FormMeta>,
+    // only forms that have a history, oldest entry first
+    history: HashMap<Box<[u8]>, Vec<SealedEntry>>,
+    // entries kept per prompt, not persisted
+    history_limit: usize,
+}
 
 impl Vault {
-    // Creates empty vault.
-    pub fn new() -> Vault {
-        Vault::default()
+    /// Creates empty vault. The header determines how the master key is derived.
+    pub fn new(header: Header) -> Vault {
+        Vault {
+            header,
+            forms: HashMap::new(),
+            meta: HashMap::new(),
+            history: HashMap::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+        }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Recovers the data key that the vault's contents are encrypted with from the master password.
+    /// Fails if the password is wrong or the KDF parameters in the header are out of range.
+    pub fn unlock(&self, password: &[u8]) -> Result<Secret, Error> {
+        self.header.unlock(password)
     }
+
+    /// Changes the master password. Only the data key is rewrapped, forms are copied untouched.
+    /// Returns the rekeyed vault and leaves self unchanged, so a failure can't leave a half
+    /// rekeyed vault behind. Fails if old_password is wrong.
+    pub fn change_password(
+        &self,
+        old_password: &[u8],
+        new_password: &[u8],
+        kdf: KdfParams,
+    ) -> Result<Vault, Error> {
+        let data_key = self.unlock(old_password)?;
+        Ok(Vault {
+            header: Header::wrap(kdf, new_password, &data_key)?,
+            forms: self.forms.clone(),
+            meta: self.meta.clone(),
+            history: self.history.clone(),
+            history_limit: self.history_limit,
+        })
+    }
+
+    /// Changes the master password and replaces the data key with a new random one.
+    /// Every form is opened with the old data key and sealed with the new one, so forms must have
+    /// been sealed with seal_form (or seal_and_insert). Form names are copied as is.
+    /// Like change_password, returns a new vault and leaves self unchanged.
+    pub fn rotate_key(
+        &self,
+        old_password: &[u8],
+        new_password: &[u8],
+        kdf: KdfParams,
+    ) -> Result<Vault, Error> {
+        let old_key = self.unlock(old_password)?;
+        let (header, new_key) = Header::new(kdf, new_password)?;
+        self.reencrypt(&old_key, header, &new_key)
+    }
+
     /// Serializes vault into tsv.
     /// The dumped string isn't a Secret, it's persisted on the filesystem anyway.
     pub fn dump(&self) -> Box<[u8]> {
+        let mut table: Vec<u8> = self.header.dump();
+        self.dump_rows(&mut table);
+        table.into_boxed_slice()
+    }
+
+    /// Deserializes data from tsv into Vault. Can only fail if string is malformed, or if it's
+    /// a sealed vault (see EncryptedVault::load).
+    pub fn load(data: &[u8]) -> Result<Self, Error> {
+        let (header, rows) = split_header(data);
+        let header = Header::load(header)?;
+        if rows.split(|byte| *byte == b'\t').next() == Some(SEALED) {
+            return Err(Error::Sealed);
+        }
+        Self::load_rows(header, rows, 2)
+    }
+
+    /// Appends one tsv row per form to table, and one per form with a history.
+    fn dump_rows(&self, table: &mut Vec<u8>) {
         // Could also parse twice to allocate the right size, then to populate, but it's easier this way.
-        let mut table: Vec<u8> = Vec::new();
-        for (name, form) in &self.0 {
+        for (name, form) in &self.forms {
             table.extend(Hex::encode(name).as_slice());
+            self.meta[name].dump(table);
             for field in form.iter() {
+                dump_field(field, table);
+            }
+            table.push(b'\n');
+        }
+        for (name, entries) in &self.history {
+            table.extend(HISTORY);
+            table.push(b'\t');
+            table.extend(Hex::encode(name).as_slice());
+            for entry in entries {
                 table.push(b'\t');
-                table.extend(Hex::encode(field.prompt.expose()).as_slice());
-                table.push(b'\t');
-                table.extend(Hex::encode(field.answer.expose()).as_slice());
+                table.extend(entry.replaced.to_string().as_bytes());
+                dump_field(&entry.field, table);
             }
             table.push(b'\n');
         }
-        table.into_boxed_slice()
     }
 
-    /// Deserializes data from tsv into Vault. Can only fail if string is malformed.
-    pub fn load(data: &[u8]) -> Option<Self> {
-        let mut vault = Self(HashMap::new());
-        for row in data.split(|byte| *byte == b'\n') {
-            let mut i = row.split(|byte| *byte == b'\t');
-            // expects name\tprompt\tanswer\tprompt\tanswer...
-            let name = i.next()?; // each row must have a form name as the first token
-            let mut form = Vec::new();
-            while let Some(prompt) = i.next() {
-                let answer = i.next()?; // each prompt must be paired with an answer
-                form.push(Field {
-                    prompt: Hex::new(prompt)?.decode(),
-                    answer: Hex::new(answer)?.decode(),
-                });
+    /// Exact length of what dump_rows appends.
+    fn rows_len(&self) -> usize {
+        let mut len = 0;
+        for (name, form) in &self.forms {
+            len += 2 * name.len() + 1 + self.meta[name].dump_len();
+            len += form.iter().map(field_len).sum::<usize>();
+        }
+        for (name, entries) in &self.history {
+            len += HISTORY.len() + 2 * name.len() + 2;
+            for entry in entries {
+                len += entry.replaced.to_string().len() + 1 + field_len(&entry.field);
             }
-            if name.is_empty() {
+        }
+        len
+    }
+
+    /// Parses rows, numbering them from first_line for error messages.
+    fn load_rows(header: Header, rows: &[u8], first_line: usize) -> Result<Self, Error> {
+        let mut vault = Self::new(header);
+        for (line, row) in tsv::lines(rows) {
+            let mut i = Tokens::new(line + first_line - 1, row);
+            // expects name\tkey=value...\tprompt\tanswer\tprompt\tanswer...
+            let name = i.require()?; // each row must have a form name as the first token
+            if name.bytes.is_empty() {
                 continue; // permit empty rows but don't add "" as a key to the map
             }
-            vault.insert(Hex::new(name)?.decode().expose(), form.into_boxed_slice());
+            if name.bytes == HISTORY {
+                // or HISTORY\tname\treplaced\tprompt\tanswer...
+                let name = i.require()?.decode_hex()?;
+                let entries = vault.history.entry(name.expose().into()).or_default();
+                while let Some(replaced) = i.next() {
+                    let replaced = str::from_utf8(replaced.bytes)
+                        .ok()
+                        .filter(|time| time.bytes().all(|byte| byte.is_ascii_digit()))
+                        .and_then(|time| time.parse().ok())
+                        .ok_or(Error::MalformedMetadata {
+                            line: replaced.line,
+                            column: replaced.column,
+                        })?;
+                    let field = load_field(i.require()?, &mut i)?;
+                    entries.push(SealedEntry { field, replaced });
+                }
+                continue;
+            }
+            let mut meta = FormMeta::default();
+            let mut form = Vec::new();
+            while let Some(prompt) = i.next() {
+                if form.is_empty() && prompt.bytes.contains(&b'=') {
+                    meta.load_token(prompt)?;
+                    continue;
+                }
+                form.push(load_field(prompt, &mut i)?);
+            }
+            // not insert, that would stamp the modified time
+            let name: Box<[u8]> = name.decode_hex()?.expose().into();
+            vault.forms.insert(name.clone(), form.into_boxed_slice());
+            vault.meta.insert(name, meta);
+        }
+        Ok(vault)
+    }
+
+    /// Opens every form and history with old_key and seals it again with new_key.
+    fn reencrypt(
+        &self,
+        old_key: &Secret,
+        header: Header,
+        new_key: &Secret,
+    ) -> Result<Vault, Error> {
+        let mut rotated = Vault::new(header);
+        for (name, form) in &self.forms {
+            let form = open_form(name, form, old_key.expose())?;
+            rotated.insert(name, seal_form(name, &form, new_key.expose())?);
+        }
+        for (name, entries) in &self.history {
+            let entries = open_history(name, entries, old_key.expose())?;
+            let entries = seal_history(name, &entries, new_key.expose())?;
+            rotated.history.insert(name.clone(), entries);
         }
-        Some(vault)
+        rotated.meta = self.meta.clone();
+        rotated.history_limit = self.history_limit;
+        Ok(rotated)
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.forms.is_empty()
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.forms.len()
     }
 
-    /// Returns Some &Form if form identified by name is present, None otherwise.
-    pub fn get(&self, name: &[u8]) -> Option<&Form> {
-        self.0.get(name)
+    /// Returns Some &SealedForm if form identified by name is present, None otherwise.
+    pub fn get(&self, name: &[u8]) -> Option<&SealedForm> {
+        self.forms.get(name)
     }
 
     /// Returns names of forms currently stored in Vault.
     pub fn form_names(&self) -> impl Iterator<Item = &[u8]> {
-        Keys(self.0.keys())
+        Keys(self.forms.keys())
+    }
+
+    /// Metadata of the form identified by name, None if it isn't present.
+    pub fn meta(&self, name: &[u8]) -> Option<&FormMeta> {
+        self.meta.get(name)
     }
 
-    /// Writes or overwrites Vault\[name\]. The burden is on the caller to construct a Form.
+    pub fn meta_mut(&mut self, name: &[u8]) -> Option<&mut FormMeta> {
+        self.meta.get_mut(name)
+    }
+
+    /// Writes or overwrites Vault\[name\]. The burden is on the caller to seal the Form.
     /// Returns None when no key was overwritten. Returns Some when a key was overwritten.
-    pub fn insert(&mut self, name: &[u8], form: Form) -> Option<Form> {
-        self.0.insert(Box::from(name), form)
+    /// Sets the form's modified time, and its created time if it's new. Other metadata is kept.
+    pub fn insert(&mut self, name: &[u8], form: SealedForm) -> Option<SealedForm> {
+        let now = now();
+        let meta = self
+            .meta
+            .entry(Box::from(name))
+            .or_insert_with(|| FormMeta {
+                created: Some(now),
+                ..FormMeta::default()
+            });
+        meta.modified = Some(now);
+        self.forms.insert(Box::from(name), form)
+    }
+
+    /// Seals form with key (see seal_form) and writes or overwrites Vault\[name\].
+    /// Fields of the overwritten form whose answer changed, or that aren't in form anymore, are
+    /// added to the form's history (see history).
+    /// Fails if encryption fails or the overwritten form doesn't decrypt, otherwise returns what
+    /// insert returns.
+    pub fn seal_and_insert(
+        &mut self,
+        name: &[u8],
+        form: &Form,
+        key: &[u8],
+    ) -> Result<Option<SealedForm>, Error> {
+        let sealed = seal_form(name, form, key)?;
+        if let Some(old) = self.forms.get(name) {
+            let old = open_form(name, old, key)?;
+            let mut history = self.history(name, key)?;
+            let replaced = now();
+            for field in old {
+                let kept = form
+                    .iter()
+                    .any(|new| new.prompt == field.prompt && new.answer == field.answer);
+                if !kept {
+                    history.push(HistoryEntry { field, replaced });
+                }
+            }
+            self.set_history(name, history, key)?;
+        }
+        Ok(self.insert(name, sealed))
+    }
+
+    /// Previous versions of the fields of the form identified by name, oldest first.
+    /// Only the newest history_limit entries of each prompt are kept.
+    /// Fails if the history doesn't decrypt, a form without a history has an empty one.
+    pub fn history(&self, name: &[u8], key: &[u8]) -> Result<Vec<HistoryEntry>, Error> {
+        match self.history.get(name) {
+            Some(entries) => open_history(name, entries, key),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Sets how many previous answers of each prompt seal_and_insert keeps, 0 keeps none.
+    /// Takes effect the next time a form's history changes.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+    }
+
+    /// Puts the field of the index-th entry of history(name) back into the form, replacing the
+    /// field with the same prompt or appending it if it was removed. Like any change, the answer
+    /// it replaces goes into the history.
+    /// Fails with FormNotFound if the form isn't present and HistoryNotFound if the entry isn't.
+    pub fn restore(&mut self, name: &[u8], key: &[u8], index: usize) -> Result<(), Error> {
+        let mut form = self.get_and_open(name, key)?.into_vec();
+        let entry = self
+            .history(name, key)?
+            .into_iter()
+            .nth(index)
+            .ok_or(Error::HistoryNotFound)?;
+        match form
+            .iter_mut()
+            .find(|field| field.prompt == entry.field.prompt)
+        {
+            Some(field) => *field = entry.field,
+            None => form.push(entry.field),
+        }
+        self.seal_and_insert(name, &form.into_boxed_slice(), key)?;
+        Ok(())
+    }
+
+    /// Moves the form identified by from to to, along with its metadata and history. Ciphertexts
+    /// are bound to the form name, so everything is sealed again.
+    /// Fails with FormNotFound if from isn't present, and with FormExists if to is, so its
+    /// history isn't lost.
+    pub fn rename(&mut self, from: &[u8], to: &[u8], key: &[u8]) -> Result<(), Error> {
+        if from == to {
+            return self.get(from).map(|_| ()).ok_or(Error::FormNotFound);
+        }
+        if self.get(to).is_some() {
+            return Err(Error::FormExists);
+        }
+        let form = seal_form(to, &self.get_and_open(from, key)?, key)?;
+        let history = seal_history(to, &self.history(from, key)?, key)?;
+        let meta = self.meta[from].clone();
+        self.remove(from);
+        self.insert(to, form);
+        let modified = self.meta[to].modified;
+        self.meta
+            .insert(Box::from(to), FormMeta { modified, ..meta });
+        if !history.is_empty() {
+            self.history.insert(Box::from(to), history);
+        }
+        Ok(())
+    }
+
+    /// Replaces the history of form name, keeping the newest history_limit entries per prompt.
+    fn set_history(
+        &mut self,
+        name: &[u8],
+        history: Vec<HistoryEntry>,
+        key: &[u8],
+    ) -> Result<(), Error> {
+        // walk from the newest entry, keeping it unless enough newer ones share its prompt
+        let mut kept: Vec<HistoryEntry> = Vec::with_capacity(history.len());
+        for entry in history.into_iter().rev() {
+            let newer = kept
+                .iter()
+                .filter(|newer| newer.field.prompt == entry.field.prompt)
+                .count();
+            if newer < self.history_limit {
+                kept.push(entry);
+            }
+        }
+        kept.reverse();
+        let history = kept;
+        if history.is_empty() {
+            self.history.remove(name);
+        } else {
+            let sealed = seal_history(name, &history, key)?;
+            self.history.insert(Box::from(name), sealed);
+        }
+        Ok(())
+    }
+
+    /// Returns the decrypted form identified by name (see open_form).
+    /// Fails with FormNotFound if the form isn't present, or Corrupt if it doesn't decrypt.
+    pub fn get_and_open(&self, name: &[u8], key: &[u8]) -> Result<Form, Error> {
+        open_form(name, self.get(name).ok_or(Error::FormNotFound)?, key)
     }
 
     /// Deletes a form in the Vault.
     /// Returns value which was removed, None if key wasn't in Vault.
-    pub fn remove(&mut self, name: &[u8]) -> Option<Form> {
-        self.0.remove(name)
+    pub fn remove(&mut self, name: &[u8]) -> Option<SealedForm> {
+        self.meta.remove(name);
+        self.history.remove(name);
+        self.forms.remove(name)
     }
 }
 
 impl Index<&[u8]> for Vault {
-    type Output = Form;
+    type Output = SealedForm;
 
     fn index(&self, key: &[u8]) -> &Self::Output {
-        &self.0[key]
+        &self.forms[key]
+    }
+}
+
+/// EncryptedVault seals an entire Vault into a single AEAD blob, so an attacker holding the file
+/// learns nothing about its structure: not the form names, how many forms there are or how many
+/// fields each one has. Only the header stays readable, it's needed to unlock the vault.
+/// The format is the Vault's header line followed by "SEALED\tblob\n" with the blob hex encoded.
+/// Before sealing, the rows are padded with empty rows to a multiple of PADDING bytes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EncryptedVault {
+    header: Header,
+    blob: Ciphertext,
+}
+
+impl EncryptedVault {
+    /// The sealed blob's size only reveals the vault's size rounded up to this many bytes.
+    const PADDING: usize = 1024;
+
+    /// Encrypts vault with key (the data key returned by Vault::unlock).
+    pub fn seal(vault: &Vault, key: &[u8]) -> Result<Self, Error> {
+        let padded_len = vault
+            .rows_len()
+            .next_multiple_of(Self::PADDING)
+            .max(Self::PADDING);
+        // allocate everything up front so the rows aren't left behind by reallocations
+        let mut rows = Zeroizing::new(Vec::with_capacity(padded_len));
+        vault.dump_rows(&mut rows);
+        debug_assert_eq!(rows.len(), vault.rows_len());
+        rows.resize(padded_len, b'\n');
+        let rows = Secret::new(std::mem::take(&mut *rows).into_boxed_slice());
+        Ok(EncryptedVault {
+            header: vault.header.clone(),
+            blob: encrypt::encrypt(rows, key)?,
+        })
+    }
+
+    /// Decrypts the vault with key (the data key returned by unlock).
+    /// Fails if the key is wrong or the blob has been tampered with.
+    /// Parse errors count lines from the start of the decrypted blob.
+    pub fn open(&self, key: &[u8]) -> Result<Vault, Error> {
+        let rows = encrypt::decrypt(self.blob.clone(), key)?;
+        Vault::load_rows(self.header.clone(), rows.expose(), 1)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Recovers the data key from the master password, see Vault::unlock.
+    pub fn unlock(&self, password: &[u8]) -> Result<Secret, Error> {
+        self.header.unlock(password)
+    }
+
+    /// Changes the master password. Only the data key is rewrapped, the blob is copied untouched.
+    /// Returns a new EncryptedVault and leaves self unchanged. Fails if old_password is wrong.
+    pub fn change_password(
+        &self,
+        old_password: &[u8],
+        new_password: &[u8],
+        kdf: KdfParams,
+    ) -> Result<Self, Error> {
+        let data_key = self.unlock(old_password)?;
+        Ok(EncryptedVault {
+            header: Header::wrap(kdf, new_password, &data_key)?,
+            blob: self.blob.clone(),
+        })
+    }
+
+    /// Changes the master password and replaces the data key, re-encrypting every field and
+    /// resealing the vault. See Vault::rotate_key.
+    pub fn rotate_key(
+        &self,
+        old_password: &[u8],
+        new_password: &[u8],
+        kdf: KdfParams,
+    ) -> Result<Self, Error> {
+        let old_key = self.unlock(old_password)?;
+        let vault = self.open(old_key.expose())?;
+        let (header, new_key) = Header::new(kdf, new_password)?;
+        let rotated = vault.reencrypt(&old_key, header, &new_key)?;
+        Self::seal(&rotated, new_key.expose())
+    }
+
+    /// Serializes the sealed vault. Like Vault::dump, the result is meant to be persisted.
+    pub fn dump(&self) -> Box<[u8]> {
+        let mut data = self.header.dump();
+        data.extend(SEALED);
+        data.push(b'\t');
+        data.extend(Hex::encode(self.blob.expose()).as_slice());
+        data.push(b'\n');
+        data.into_boxed_slice()
+    }
+
+    /// Deserializes a sealed vault. Can only fail if data is malformed, or if it's a vault that
+    /// isn't sealed (see Vault::load).
+    pub fn load(data: &[u8]) -> Result<Self, Error> {
+        let (header, rest) = split_header(data);
+        let header = Header::load(header)?;
+        let mut tokens = Tokens::new(2, rest.strip_suffix(b"\n").unwrap_or(rest));
+        if tokens.require()?.bytes != SEALED {
+            return Err(Error::NotSealed);
+        }
+        let token = tokens.require()?;
+        let blob = token.decode_ciphertext()?;
+        // seal always pads, so any other size means the blob was cut short or added to
+        let rows_len = blob.plaintext_len();
+        if rows_len == 0 || !rows_len.is_multiple_of(Self::PADDING) {
+            return Err(Error::TruncatedCiphertext {
+                line: token.line,
+                column: token.column,
+            });
+        }
+        if let Some(token) = tokens.next() {
+            return Err(Error::TrailingData {
+                line: token.line,
+                column: token.column,
+            });
+        }
+        Ok(EncryptedVault { header, blob })
+    }
+}
+
+/// Appends '\t' and a field, see Vault.
+fn dump_field(field: &SealedField, row: &mut Vec<u8>) {
+    row.push(b'\t');
+    if let Some(kind) = field.kind {
+        row.extend(kind.name().as_bytes());
+        row.push(b':');
+    }
+    row.extend(Hex::encode(field.prompt.expose()).as_slice());
+    row.push(b'\t');
+    row.extend(Hex::encode(field.answer.expose()).as_slice());
+}
+
+/// Exact length of what dump_field appends.
+fn field_len(field: &SealedField) -> usize {
+    let kind_len = field.kind.map_or(0, |kind| kind.name().len() + 1);
+    2 * (field.prompt.expose().len() + field.answer.expose().len()) + 2 + kind_len
+}
+
+/// Parses the field starting at prompt, taking its answer from tokens.
+fn load_field(prompt: Token, tokens: &mut Tokens) -> Result<SealedField, Error> {
+    let (kind, prompt) = prompt.split_kind()?;
+    let answer = tokens.require()?; // each prompt must be paired with an answer
+    Ok(SealedField {
+        prompt: prompt.decode_ciphertext()?,
+        answer: answer.decode_ciphertext()?,
+        kind,
+    })
+}
+
+fn seal_history(
+    name: &[u8],
+    entries: &[HistoryEntry],
+    key: &[u8],
+) -> Result<Vec<SealedEntry>, Error> {
+    let mut sealed = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        sealed.push(SealedEntry {
+            field: seal_field(name, i, &entry.field, key, true)?,
+            replaced: entry.replaced,
+        });
+    }
+    Ok(sealed)
+}
+
+fn open_history(
+    name: &[u8],
+    entries: &[SealedEntry],
+    key: &[u8],
+) -> Result<Vec<HistoryEntry>, Error> {
+    let mut opened = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        opened.push(HistoryEntry {
+            field: open_field(name, i, &entry.field, key, true)?,
+            replaced: entry.replaced,
+        });
+    }
+    Ok(opened)
+}
+
+/// Marks a row holding a form's history, it isn't valid hex either.
+const HISTORY: &[u8] = b"HISTORY";
+
+/// Marks the row holding an EncryptedVault's blob. It isn't valid hex, so Vault::load rejects
+/// sealed vaults instead of mistaking the blob for a form name.
+const SEALED: &[u8] = b"SEALED";
+
+/// Splits serialized data into the header line and everything after it.
+fn split_header(data: &[u8]) -> (&[u8], &[u8]) {
+    match data.iter().position(|byte| *byte == b'\n') {
+        Some(i) => (&data[..i], &data[i + 1..]),
+        None => (data, &data[data.len()..]),
     }
 }
 
 /// vault::Keys Iterator adapts hash_map::Iter by auto deref-ing the Box into &str
-pub struct Keys<'a>(std::collections::hash_map::Keys<'a, Box<[u8]>, Form>);
+pub struct Keys<'a>(std::collections::hash_map::Keys<'a, Box<[u8]>, SealedForm>);
 
 impl<'a> Iterator for Keys<'a> {
     type Item = &'a [u8];
@@ -124,42 +999,94 @@ impl<'a> Iterator for Keys<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encrypt::KdfParams;
+    use crate::test_util::{FAST_KDF, field, test_header};
+
+    fn corrupt(form: &[u8], index: usize, history: bool) -> Error {
+        Error::Corrupt {
+            form: form.into(),
+            index,
+            history,
+        }
+    }
 
     #[test]
     fn serialize_empty_vault() {
-        let empty = b"";
-        let vault = Vault::load(empty).unwrap();
+        let header = test_header().0;
+        let empty = header.dump();
+        let vault = Vault::load(&empty).unwrap();
         assert!(vault.is_empty());
-        assert_eq!(&*vault.dump(), empty);
+        assert_eq!(*vault.dump(), *empty);
 
-        let tabs = b"\t\t\t\t\n";
-        let vault = Vault::load(tabs).unwrap();
+        // header without trailing newline
+        let vault = Vault::load(&empty[..empty.len() - 1]).unwrap();
         assert!(vault.is_empty());
-        assert_eq!(&*vault.dump(), empty);
+        assert_eq!(*vault.dump(), *empty);
 
-        let newlines = b"\n\n\n\n\n";
-        let vault = Vault::load(newlines).unwrap();
+        let tabs = [&empty[..], b"\t\t\t\t\n"].concat();
+        let vault = Vault::load(&tabs).unwrap();
         assert!(vault.is_empty());
-        assert_eq!(&*vault.dump(), empty);
+        assert_eq!(*vault.dump(), *empty);
 
-        let devault = Vault::default();
+        let newlines = [&empty[..], b"\n\n\n\n\n"].concat();
+        let vault = Vault::load(&newlines).unwrap();
         assert!(vault.is_empty());
-        assert_eq!(&*devault.dump(), empty);
+        assert_eq!(*vault.dump(), *empty);
+
+        let new = Vault::new(header);
+        assert!(new.is_empty());
+        assert_eq!(*new.dump(), *empty);
+    }
+
+    #[test]
+    fn reject_missing_header() {
+        assert_eq!(Vault::load(b""), Err(Error::NotAVault));
+        assert_eq!(Vault::load(b"\n\n\n"), Err(Error::NotAVault));
+        let row = [
+            Hex::encode(b"irc").as_slice(),
+            b"\t",
+            Hex::encode(b"username").as_slice(),
+            b"\t",
+            Hex::encode(b"AzureDiamond").as_slice(),
+            b"\n",
+        ]
+        .concat();
+        assert_eq!(Vault::load(&row), Err(Error::NotAVault));
+    }
+
+    #[test]
+    fn header_survives_round_trip() {
+        let (header, data_key) = Header::new(KdfParams::default(), b"hunter2").unwrap();
+        let vault = Vault::new(header);
+        let loaded = Vault::load(&vault.dump()).unwrap();
+        assert_eq!(loaded.header(), vault.header());
+        assert_eq!(loaded.unlock(b"hunter2"), Ok(data_key));
+        assert_eq!(loaded.unlock(b"hunter3"), Err(Error::Authentication));
     }
 
     #[test]
     fn serialize_basic_vault() {
         let serialized_raw = b"irc\tusername\tAzureDiamond\tpassword\thunter2\tWho's your best friend?\tCthon98\nother website dot com\tusername\tCthon98\tpassword\t*********\tWho's your best friend?\tAzureDiamond\n";
-        // It needs to be encoded in hex
+        // Prompts and answers need to be encrypted, then everything needs to be encoded in hex
+        let key = encrypt::generate_key().unwrap();
         let lines = serialized_raw.split(|byte| *byte == b'\n');
-        let mut serialized_hex = Vec::new();
+        let mut serialized_hex = test_header().0.dump();
         for line in lines {
-            let tokens = line.split(|byte| *byte == b'\t');
-            for token in tokens {
-                serialized_hex.extend(Hex::encode(token).as_slice());
+            let mut tokens = line.split(|byte| *byte == b'\t');
+            let name = tokens.next().unwrap();
+            serialized_hex.extend(Hex::encode(name).as_slice());
+            for (i, token) in tokens.enumerate() {
+                let role = if i % 2 == 0 {
+                    Role::Prompt
+                } else {
+                    Role::Answer
+                };
+                let aad = field_aad(name, i / 2, role, None);
+                let sealed =
+                    encrypt::encrypt_with_aad(Secret::new(token.into()), key.expose(), &aad);
                 serialized_hex.push(b'\t');
+                serialized_hex.extend(Hex::encode(sealed.unwrap().expose()).as_slice());
             }
-            serialized_hex.pop();
             serialized_hex.push(b'\n');
         }
         println!("{}", String::from_utf8_lossy(serialized_hex.as_slice()));
@@ -173,7 +1100,7 @@ mod tests {
         }
         assert_eq!(names.next(), None);
 
-        let first_form = &vault[b"irc"];
+        let first_form = vault.get_and_open(b"irc", key.expose()).unwrap();
         assert_eq!(first_form[0].prompt.expose(), b"username");
         assert_eq!(first_form[0].answer.expose(), b"AzureDiamond");
         assert_eq!(first_form[1].prompt.expose(), b"password");
@@ -181,7 +1108,9 @@ mod tests {
         assert_eq!(first_form[2].prompt.expose(), b"Who's your best friend?");
         assert_eq!(first_form[2].answer.expose(), b"Cthon98");
 
-        let second_form = &vault[b"other website dot com"];
+        let second_form = vault
+            .get_and_open(b"other website dot com", key.expose())
+            .unwrap();
         assert_eq!(second_form[0].prompt.expose(), b"username");
         assert_eq!(second_form[0].answer.expose(), b"Cthon98");
         assert_eq!(second_form[1].prompt.expose(), b"password");
@@ -199,24 +1128,83 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reject_plaintext_fields() {
+        // plaintext too short to be a ciphertext
+        let mut serialized_hex = test_header().0.dump();
+        for token in [&b"irc"[..], b"username", b"AzureDiamond"] {
+            serialized_hex.extend(Hex::encode(token).as_slice());
+            serialized_hex.push(b'\t');
+        }
+        serialized_hex.pop();
+        let column = 2 * b"irc".len() + 2;
+        assert_eq!(
+            Vault::load(&serialized_hex),
+            Err(Error::TruncatedCiphertext { line: 2, column })
+        );
+    }
+
+    #[test]
+    fn reject_malformed_rows() {
+        let key = encrypt::generate_key().unwrap();
+        let ciphertext = encrypt::encrypt(Secret::new((*b"hunter2").into()), key.expose()).unwrap();
+        let ciphertext = Hex::encode(ciphertext.expose());
+        let ciphertext = str::from_utf8(ciphertext.as_slice()).unwrap();
+        let header = test_header().0.dump();
+        let reject = [
+            // prompt without an answer
+            (
+                format!("A0\t{ciphertext}\n"),
+                Error::TruncatedRow { line: 2 },
+            ),
+            (
+                format!("A0\t{ciphertext}\t{ciphertext}\nB0\t{ciphertext}\n"),
+                Error::TruncatedRow { line: 3 },
+            ),
+            // lowercase hex in the name, odd length answer (reported just past its end)
+            (
+                "a0\n".to_string(),
+                Error::MalformedHex { line: 2, column: 1 },
+            ),
+            (
+                format!("A0\t{ciphertext}\t{ciphertext}0\n"),
+                Error::MalformedHex {
+                    line: 2,
+                    column: 5 + ciphertext.len() + ciphertext.len() + 1,
+                },
+            ),
+            ("SEALED\t00\n".to_string(), Error::Sealed),
+        ];
+        for (rows, error) in reject {
+            let data = [&header[..], rows.as_bytes()].concat();
+            assert_eq!(Vault::load(&data), Err(error), "{rows}");
+        }
+    }
+
     #[test]
     fn modify_vault() {
-        let mut vault = Vault::default();
+        let key = encrypt::generate_key().unwrap();
+        let key = key.expose();
+        let mut vault = Vault::new(test_header().0);
         assert!(vault.is_empty());
 
         let generic_username = Field {
             prompt: Secret::new(Box::from(*b"username")),
             answer: Secret::new(Box::from(*b"user1@example.test")),
+            kind: Some(FieldKind::Email),
         };
 
         let bad_password = Field {
             prompt: Secret::new(Box::from(*b"password")),
             answer: Secret::new(Box::from(*b"password1")),
+            kind: None,
         };
-        vault.insert(
+        let overwritten = vault.seal_and_insert(
             b"asdf",
-            [generic_username.clone(), bad_password.clone()].into(),
+            &[generic_username.clone(), bad_password.clone()].into(),
+            key,
         );
+        assert_eq!(overwritten, Ok(None));
 
         assert_eq!(vault.len(), 1);
         assert_eq!(vault.get(b"form name that wasn't inserted"), None);
@@ -225,8 +1213,8 @@ mod tests {
             assert_eq!(form_name, b"asdf");
         }
 
-        let form = vault.get(b"asdf").unwrap();
-        for field in form {
+        let form = vault.get_and_open(b"asdf", key).unwrap();
+        for field in &form {
             assert!(field.prompt == generic_username.prompt || field.prompt == bad_password.prompt);
             assert!(field.answer == generic_username.answer || field.answer == bad_password.answer);
         }
@@ -237,4 +1225,430 @@ mod tests {
         vault.remove(b"asdf");
         assert!(vault.is_empty());
     }
+
+    #[test]
+    fn rekey_vault() {
+        let (header, key) = test_header();
+        let mut vault = Vault::new(header);
+        let form: Form = [field(b"password", b"hunter2")].into();
+        vault.seal_and_insert(b"irc", &form, key.expose()).unwrap();
+
+        // wrong password leaves nothing to write back
+        assert_eq!(
+            vault.change_password(b"hunter3", b"*******", FAST_KDF),
+            Err(Error::Authentication)
+        );
+        assert_eq!(
+            vault.rotate_key(b"hunter3", b"*******", FAST_KDF),
+            Err(Error::Authentication)
+        );
+
+        // password change keeps data key and ciphertexts
+        let changed = vault
+            .change_password(b"hunter2", b"*******", FAST_KDF)
+            .unwrap();
+        assert_eq!(changed.unlock(b"hunter2"), Err(Error::Authentication));
+        assert_eq!(changed.unlock(b"*******"), Ok(key.clone()));
+        assert_eq!(changed[b"irc"], vault[b"irc"]);
+        // old vault is untouched
+        assert_eq!(vault.unlock(b"hunter2"), Ok(key.clone()));
+
+        // key rotation re-encrypts everything with a new data key
+        let rotated = vault.rotate_key(b"hunter2", b"*******", FAST_KDF).unwrap();
+        let new_key = rotated.unlock(b"*******").unwrap();
+        assert_ne!(new_key, key);
+        assert_ne!(rotated[b"irc"], vault[b"irc"]);
+        assert_eq!(rotated.get_and_open(b"irc", new_key.expose()), Ok(form));
+        // a field can't tell the old key from tampering
+        assert_eq!(
+            rotated.get_and_open(b"irc", key.expose()),
+            Err(corrupt(b"irc", 0, false))
+        );
+    }
+
+    #[test]
+    fn seal_vault() {
+        let (header, key) = test_header();
+        let mut vault = Vault::new(header);
+        let empty = EncryptedVault::seal(&vault, key.expose()).unwrap();
+
+        let irc: Form = [
+            field(b"username", b"AzureDiamond"),
+            field(b"password", b"hunter2"),
+        ]
+        .into();
+        let bank: Form = [field(b"password", b"hunter2")].into();
+        vault.seal_and_insert(b"irc", &irc, key.expose()).unwrap();
+        vault.seal_and_insert(b"bank", &bank, key.expose()).unwrap();
+        let sealed = EncryptedVault::seal(&vault, key.expose()).unwrap();
+
+        // nothing about the structure leaks, not even the size for small vaults
+        assert_eq!(sealed.dump().len(), empty.dump().len());
+        let dumped = sealed.dump();
+        assert_eq!(dumped.iter().filter(|byte| **byte == b'\n').count(), 2);
+        assert!(
+            !dumped
+                .windows(6)
+                .any(|w| w == Hex::encode(b"irc").as_slice())
+        );
+
+        // round trip
+        let loaded = EncryptedVault::load(&dumped).unwrap();
+        assert_eq!(loaded, sealed);
+        let key = loaded.unlock(b"hunter2").unwrap();
+        assert_eq!(loaded.open(key.expose()).unwrap(), vault);
+        assert_eq!(loaded.unlock(b"hunter3"), Err(Error::Authentication));
+        assert_eq!(
+            loaded.open(encrypt::generate_key().unwrap().expose()),
+            Err(Error::Authentication)
+        );
+
+        // sealed and unsealed vaults can't be confused with each other
+        assert_eq!(Vault::load(&dumped), Err(Error::Sealed));
+        assert_eq!(EncryptedVault::load(&vault.dump()), Err(Error::NotSealed));
+        assert_eq!(
+            EncryptedVault::load(&empty.header.dump()),
+            Err(Error::NotSealed)
+        );
+        let trailing = [&dumped[..dumped.len() - 1], b"\t00\n"].concat();
+        assert_eq!(
+            EncryptedVault::load(&trailing),
+            Err(Error::TrailingData {
+                line: 2,
+                column: dumped.len() - empty.header.dump().len() + 1,
+            })
+        );
+
+        // a blob cut short by whole bytes still decodes, but isn't a whole number of blocks
+        let column = SEALED.len() + 2;
+        for cut in [2, 2 * EncryptedVault::PADDING] {
+            let truncated = [&dumped[..dumped.len() - 1 - cut], b"\n"].concat();
+            assert_eq!(
+                EncryptedVault::load(&truncated),
+                Err(Error::TruncatedCiphertext { line: 2, column })
+            );
+        }
+
+        // password change doesn't touch the blob
+        let changed = sealed
+            .change_password(b"hunter2", b"*******", FAST_KDF)
+            .unwrap();
+        assert_eq!(changed.blob, sealed.blob);
+        assert_eq!(changed.unlock(b"*******"), Ok(key.clone()));
+        assert_eq!(
+            sealed.change_password(b"hunter3", b"*******", FAST_KDF),
+            Err(Error::Authentication)
+        );
+
+        // key rotation reseals everything
+        let rotated = sealed.rotate_key(b"hunter2", b"*******", FAST_KDF).unwrap();
+        let new_key = rotated.unlock(b"*******").unwrap();
+        assert_ne!(new_key, key);
+        assert_eq!(rotated.open(key.expose()), Err(Error::Authentication));
+        let opened = rotated.open(new_key.expose()).unwrap();
+        assert_eq!(opened.get_and_open(b"irc", new_key.expose()), Ok(irc));
+    }
+
+    #[test]
+    fn ciphertexts_are_bound_to_their_slot() {
+        let key = encrypt::generate_key().unwrap();
+        let key = key.expose();
+        let mut vault = Vault::new(test_header().0);
+        let irc: Form = [
+            field(b"username", b"AzureDiamond"),
+            field(b"password", b"hunter2"),
+        ]
+        .into();
+        vault.seal_and_insert(b"irc", &irc, key).unwrap();
+        vault.seal_and_insert(b"bank", &irc, key).unwrap();
+        assert_eq!(vault.get_and_open(b"irc", key), Ok(irc.clone()));
+        assert_eq!(
+            vault.get_and_open(b"missing", key),
+            Err(Error::FormNotFound)
+        );
+
+        // same form under another name
+        let moved = vault[b"irc"].clone();
+        vault.insert(b"bank", moved);
+        assert_eq!(
+            vault.get_and_open(b"bank", key),
+            Err(corrupt(b"bank", 0, false))
+        );
+
+        // fields reordered
+        let mut swapped = vault[b"irc"].clone();
+        swapped.swap(0, 1);
+        vault.insert(b"bank", swapped);
+        assert_eq!(
+            vault.get_and_open(b"bank", key),
+            Err(corrupt(b"bank", 0, false))
+        );
+
+        // prompt and answer swapped
+        let mut flipped = vault[b"irc"].clone();
+        std::mem::swap(&mut flipped[1].prompt, &mut flipped[1].answer);
+        vault.insert(b"irc", flipped);
+        assert_eq!(
+            vault.get_and_open(b"irc", key),
+            Err(corrupt(b"irc", 1, false))
+        );
+    }
+
+    #[test]
+    fn field_kinds() {
+        let key = encrypt::generate_key().unwrap();
+        let key = key.expose();
+        let mut vault = Vault::new(test_header().0);
+        let mut irc = vec![
+            field(b"username", b"AzureDiamond"),
+            field(b"password", b"hunter2"),
+        ];
+        irc[1].kind = Some(FieldKind::Password);
+        let irc: Form = irc.into();
+        vault.seal_and_insert(b"irc", &irc, key).unwrap();
+
+        // only the field with a kind gets a prefix
+        let dumped = vault.dump();
+        assert_eq!(dumped.windows(9).filter(|w| w == b"password:").count(), 1);
+        let loaded = Vault::load(&dumped).unwrap();
+        assert_eq!(loaded, vault);
+        assert_eq!(loaded.get_and_open(b"irc", key), Ok(irc.clone()));
+        let sealed = EncryptedVault::seal(&vault, key).unwrap();
+        assert_eq!(sealed.open(key), Ok(vault.clone()));
+
+        // the answer is bound to its kind
+        let mut relabeled = vault[b"irc"].clone();
+        relabeled[1].kind = Some(FieldKind::Visible);
+        vault.insert(b"irc", relabeled);
+        assert_eq!(
+            vault.get_and_open(b"irc", key),
+            Err(corrupt(b"irc", 1, false))
+        );
+
+        let header = test_header().0.dump();
+        let unknown = [&header[..], b"A0\tpasswd:00\t00\n"].concat();
+        assert_eq!(
+            Vault::load(&unknown),
+            Err(Error::UnknownFieldKind { line: 2, column: 4 })
+        );
+
+        for kind in FieldKind::ALL {
+            assert_eq!(FieldKind::from_name(kind.name().as_bytes()), Some(kind));
+        }
+        assert_eq!(FieldKind::from_name(b"Password"), None);
+        let guesses: [(&[u8], &[u8], _); 6] = [
+            (b"Username", b"AzureDiamond", Some(FieldKind::Login)),
+            (b"Email password", b"hunter2", Some(FieldKind::Password)),
+            (b"E-mail", b"a@example.test", Some(FieldKind::Email)),
+            (b"2fa", b"otpauth://totp/x?secret=AA", Some(FieldKind::Otp)),
+            (b"seed", b"otpauth://totp/x?secret=AA", Some(FieldKind::Otp)),
+            (b"Who's your best friend?", b"Cthon98", None),
+        ];
+        for (prompt, answer, kind) in guesses {
+            assert_eq!(FieldKind::guess(prompt, answer), kind);
+        }
+    }
+
+    #[test]
+    fn form_metadata() {
+        let (header, key) = test_header();
+        let mut vault = Vault::new(header);
+        let irc: Form = [field(b"password", b"hunter2")].into();
+        vault.seal_and_insert(b"irc", &irc, key.expose()).unwrap();
+        let meta = vault.meta(b"irc").unwrap().clone();
+        assert!(meta.created.is_some());
+        assert_eq!(meta.created, meta.modified);
+        assert_eq!(vault.meta(b"bank"), None);
+
+        // overwriting keeps everything but the modified time
+        let meta = vault.meta_mut(b"irc").unwrap();
+        meta.created = Some(1);
+        meta.modified = Some(1);
+        meta.folder = Some((*b"chat/ircnet").into());
+        assert!(meta.add_tag(b"work"));
+        assert!(meta.add_tag(b"old\tnet"));
+        assert!(!meta.add_tag(b"work"));
+        meta.favorite = true;
+        meta.mark_used();
+        vault.seal_and_insert(b"irc", &irc, key.expose()).unwrap();
+        let meta = vault.meta(b"irc").unwrap().clone();
+        assert_eq!(meta.created, Some(1));
+        assert!(meta.modified > Some(1));
+        assert!(meta.last_used.is_some());
+
+        assert!(meta.has_tag(b"work") && !meta.has_tag(b"wor"));
+        assert!(
+            meta.in_folder(b"chat") && meta.in_folder(b"chat/") && meta.in_folder(b"chat/ircnet")
+        );
+        assert!(!meta.in_folder(b"cha") && !meta.in_folder(b"chat/ircnet/efnet"));
+
+        // round trips, and survives rekeying
+        assert_eq!(Vault::load(&vault.dump()), Ok(vault.clone()));
+        let sealed = EncryptedVault::seal(&vault, key.expose()).unwrap();
+        assert_eq!(sealed.open(key.expose()), Ok(vault.clone()));
+        let rotated = vault.rotate_key(b"hunter2", b"*******", FAST_KDF).unwrap();
+        assert_eq!(rotated.meta(b"irc"), Some(&meta));
+        let changed = vault
+            .change_password(b"hunter2", b"*******", FAST_KDF)
+            .unwrap();
+        assert_eq!(changed.meta(b"irc"), Some(&meta));
+
+        let mut removed = vault.clone();
+        removed.remove(b"irc");
+        assert_eq!(removed.meta(b"irc"), None);
+
+        // rows without metadata tokens have none, and loading doesn't stamp them
+        let header = test_header().0.dump();
+        let ciphertext = Hex::encode(vault[b"irc"][0].prompt.expose());
+        let ciphertext = str::from_utf8(ciphertext.as_slice()).unwrap();
+        let old = format!("A0\t{ciphertext}\t{ciphertext}\n");
+        let old = Vault::load(&[&header[..], old.as_bytes()].concat()).unwrap();
+        assert_eq!(old.meta(b"\x0A"), Some(&FormMeta::default()));
+
+        let reject = [
+            ("A0\tcreated=x\n", 12),
+            ("A0\tcreated=-1\n", 12),
+            ("A0\tfavorite=0\n", 4),
+            ("A0\tcolor=1\n", 4),
+        ];
+        for (row, column) in reject {
+            let data = [&header[..], row.as_bytes()].concat();
+            assert_eq!(
+                Vault::load(&data),
+                Err(Error::MalformedMetadata { line: 2, column }),
+                "{row}"
+            );
+        }
+        let data = [&header[..], b"A0\ttag=7\n"].concat();
+        assert_eq!(
+            Vault::load(&data),
+            Err(Error::MalformedHex { line: 2, column: 9 })
+        );
+    }
+
+    #[test]
+    fn field_history() {
+        let (header, key) = test_header();
+        let key = key.expose();
+        let mut vault = Vault::new(header);
+        vault.set_history_limit(2);
+        let irc = |password: &[u8]| -> Form {
+            [
+                field(b"user", b"AzureDiamond"),
+                field(b"password", password),
+            ]
+            .into()
+        };
+        vault
+            .seal_and_insert(b"irc", &irc(b"hunter1"), key)
+            .unwrap();
+        assert_eq!(vault.history(b"irc", key), Ok(Vec::new()));
+        // unchanged fields aren't recorded
+        vault
+            .seal_and_insert(b"irc", &irc(b"hunter1"), key)
+            .unwrap();
+        assert_eq!(vault.history(b"irc", key), Ok(Vec::new()));
+
+        for password in [&b"hunter2"[..], b"hunter3", b"hunter4"] {
+            vault.seal_and_insert(b"irc", &irc(password), key).unwrap();
+        }
+        // only the newest two of each prompt are kept
+        let answers = |vault: &Vault, name: &[u8]| -> Vec<Vec<u8>> {
+            let history = vault.history(name, key).unwrap();
+            history
+                .iter()
+                .map(|entry| entry.field.answer.expose().to_vec())
+                .collect()
+        };
+        assert_eq!(answers(&vault, b"irc"), [&b"hunter2"[..], b"hunter3"]);
+
+        // removed fields are recorded too, and come back with restore
+        vault
+            .seal_and_insert(b"irc", &[field(b"password", b"hunter4")].into(), key)
+            .unwrap();
+        assert_eq!(
+            answers(&vault, b"irc"),
+            [&b"hunter2"[..], b"hunter3", b"AzureDiamond"]
+        );
+        vault.restore(b"irc", key, 0).unwrap();
+        let form = vault.get_and_open(b"irc", key).unwrap();
+        assert_eq!(*form, [field(b"password", b"hunter2")]);
+        assert_eq!(
+            answers(&vault, b"irc"),
+            [&b"hunter3"[..], b"AzureDiamond", b"hunter4"]
+        );
+        let user = answers(&vault, b"irc").len() - 2;
+        vault.restore(b"irc", key, user).unwrap();
+        assert_eq!(vault.get_and_open(b"irc", key).unwrap().len(), 2);
+        assert_eq!(vault.restore(b"irc", key, 9), Err(Error::HistoryNotFound));
+        assert_eq!(vault.restore(b"bank", key, 0), Err(Error::FormNotFound));
+
+        // the history is persisted, encrypted and survives rekeying and renaming
+        let history = vault.history(b"irc", key).unwrap();
+        let dumped = vault.dump();
+        let plaintext = Hex::encode(b"hunter3");
+        let plaintext = plaintext.as_slice();
+        assert!(!dumped.windows(plaintext.len()).any(|w| w == plaintext));
+        let mut loaded = Vault::load(&dumped).unwrap();
+        loaded.set_history_limit(2);
+        assert_eq!(loaded, vault);
+        // a history entry only opens in its own slot, not as a current field or another form's
+        let entry = &loaded.history[&b"irc"[..]][0].field;
+        assert_eq!(
+            open_field(b"irc", 0, entry, key, true)
+                .unwrap()
+                .answer
+                .expose(),
+            b"hunter3"
+        );
+        assert!(open_field(b"irc", 0, entry, key, false).is_err());
+        assert!(open_field(b"chat", 0, entry, key, true).is_err());
+        let sealed = EncryptedVault::seal(&vault, key).unwrap();
+        assert_eq!(
+            sealed.open(key).unwrap().history(b"irc", key),
+            Ok(history.clone())
+        );
+        let rotated = vault.rotate_key(b"hunter2", b"*******", FAST_KDF).unwrap();
+        let new_key = rotated.unlock(b"*******").unwrap();
+        assert_eq!(
+            rotated.history(b"irc", new_key.expose()),
+            Ok(history.clone())
+        );
+        // renaming onto another form would lose that form's history
+        vault
+            .seal_and_insert(b"chat", &irc(b"hunter9"), key)
+            .unwrap();
+        assert_eq!(vault.rename(b"irc", b"chat", key), Err(Error::FormExists));
+        assert_eq!(vault.get_and_open(b"chat", key), Ok(irc(b"hunter9")));
+        assert!(vault.get(b"irc").is_some());
+        vault.remove(b"chat");
+        vault.rename(b"irc", b"chat", key).unwrap();
+        assert_eq!(vault.get(b"irc"), None);
+        assert_eq!(vault.history(b"irc", key), Ok(Vec::new()));
+        assert_eq!(vault.history(b"chat", key), Ok(history));
+
+        // entries are bound to their slot like fields are
+        let mut moved = vault.clone();
+        let entries = moved.history.remove(&b"chat"[..]).unwrap();
+        moved.history.insert(Box::from(&b"irc"[..]), entries);
+        assert_eq!(moved.history(b"irc", key), Err(corrupt(b"irc", 0, true)));
+        let mut swapped = vault.clone();
+        swapped.history.get_mut(&b"chat"[..]).unwrap().swap(0, 1);
+        assert_eq!(
+            swapped.history(b"chat", key),
+            Err(corrupt(b"chat", 0, true))
+        );
+
+        // removing a form drops its history, a limit of 0 keeps none
+        vault.remove(b"chat");
+        assert_eq!(vault.history(b"chat", key), Ok(Vec::new()));
+        vault.set_history_limit(0);
+        vault
+            .seal_and_insert(b"irc", &irc(b"hunter1"), key)
+            .unwrap();
+        vault
+            .seal_and_insert(b"irc", &irc(b"hunter2"), key)
+            .unwrap();
+        assert_eq!(vault.history(b"irc", key), Ok(Vec::new()));
+    }
 }
@@ -1,20 +1,20 @@
 use passmogu::{
-    encrypt::{decrypt, derive_key, encrypt},
+    encrypt::KdfParams,
     generate,
+    header::Header,
     secret::Secret,
-    vault::{Field, Vault},
+    vault::{EncryptedVault, Field, FieldKind, Vault},
 };
 
 #[test]
 #[allow(clippy::vec_init_then_push)]
 fn basic_usage() {
-    // Set the master password
+    // Set the master password, the header picks a random salt and wraps a random data key with it
     let master_password = Secret::new((*b"hunter2").into());
-    let salt = b"arbitrary salt, just don't repeat it";
-    let master_key = derive_key(master_password.expose(), salt);
+    let (header, master_key) = Header::new(KdfParams::default(), master_password.expose()).unwrap();
+    let mut vault = Vault::new(header);
     drop(master_password);
 
-    let mut vault = Vault::new();
     let websites: [&[u8]; 4] = [
         b"your-bank.tld",
         b"the-irs.tld",
@@ -25,18 +25,22 @@ fn basic_usage() {
         Field {
             prompt: Secret::new((*b"Username").into()),
             answer: Secret::new((*b"AzureDiamond").into()),
+            kind: None,
         },
         Field {
             prompt: Secret::new((*b"Credit Card Number").into()),
             answer: Secret::new((*b"5555555555555555").into()),
+            kind: None,
         },
         Field {
             prompt: Secret::new((*b"Social Security Number").into()),
             answer: Secret::new((*b"5555555555").into()),
+            kind: None,
         },
         Field {
             prompt: Secret::new((*b"What's your mother's maiden name?").into()),
             answer: Secret::new((*b"Your mom!").into()),
+            kind: None,
         },
     ];
     for site in websites {
@@ -47,40 +51,47 @@ fn basic_usage() {
         plaintext_form.push(Field {
             prompt: Secret::new((*b"Password").into()),
             answer: password,
+            kind: Some(FieldKind::Password),
         });
         for field in &mock_fields {
             plaintext_form.push(field.clone());
         }
 
-        // Encrypt form data
-        let mut encrypted_form: Vec<Field> = Vec::new();
-        for form in plaintext_form {
-            let prompt = encrypt(form.prompt, master_key.expose()).unwrap();
-            let answer = encrypt(form.answer, master_key.expose()).unwrap();
-
-            encrypted_form.push(Field { prompt, answer });
-        }
-        let form_name = Secret::new(site.into());
-
-        // Save form into vault
-        vault.insert(
-            encrypt(form_name, master_key.expose()).unwrap().expose(),
-            encrypted_form.into_boxed_slice(),
-        );
+        // Encrypt form data and save form into vault.
+        // Each ciphertext is bound to the form name and its position in the form.
+        vault
+            .seal_and_insert(
+                site,
+                &plaintext_form.into_boxed_slice(),
+                master_key.expose(),
+            )
+            .unwrap();
     }
 
-    // Dump vault and load it back
-    let serialized = vault.dump();
-    let loaded = Vault::load(&serialized).unwrap();
+    // Seal the whole vault so form names aren't persisted in plaintext, dump it and load it back
+    let serialized = EncryptedVault::seal(&vault, master_key.expose())
+        .unwrap()
+        .dump();
+    let sealed = EncryptedVault::load(&serialized).unwrap();
+    // the master key can be recovered from just the password
+    assert_eq!(sealed.unlock(b"hunter2").unwrap(), master_key);
+    assert_eq!(
+        sealed.unlock(b"hunter3"),
+        Err(passmogu::Error::Authentication)
+    );
+    let loaded = sealed.open(master_key.expose()).unwrap();
+    assert_eq!(loaded, vault);
 
     for form_name in loaded.form_names() {
-        let name = decrypt(Secret::new(form_name.into()), master_key.expose()).unwrap();
-        assert!(websites.contains(&name.expose()));
-        println!("\nform: {}", str::from_utf8(name.expose()).unwrap());
-        let form = &vault[form_name];
+        assert!(websites.contains(&form_name));
+        println!("\nform: {}", str::from_utf8(form_name).unwrap());
+        let form = loaded.get_and_open(form_name, master_key.expose()).unwrap();
         for field in form {
-            let prompt = decrypt(field.prompt.clone(), master_key.expose()).unwrap();
-            let answer = decrypt(field.answer.clone(), master_key.expose()).unwrap();
+            let Field {
+                prompt,
+                answer,
+                kind,
+            } = field;
             assert_ne!(prompt, answer);
             println!(
                 "{} {}",
@@ -88,8 +99,14 @@ fn basic_usage() {
                 str::from_utf8(answer.expose()).unwrap()
             );
             // we randomly generated passwords, so we don't know what they are
-            if prompt.expose() != b"Password" {
-                assert!(mock_fields.contains(&Field { prompt, answer }));
+            if prompt.expose() == b"Password" {
+                assert_eq!(kind, Some(FieldKind::Password));
+            } else {
+                assert!(mock_fields.contains(&Field {
+                    prompt,
+                    answer,
+                    kind
+                }));
             }
         }
     }